@@ -9,3 +9,189 @@ pub mod scheduler {
 
 // Re-export commonly used types for convenience
 pub use scheduler::*;
+
+/// Canonical names of the languages the system knows how to compile or run
+pub const LANGUAGES: &[&str] = &[
+    "c",
+    "cpp",
+    "rust",
+    "go",
+    "java",
+    "python",
+    "javascript",
+    "ruby",
+];
+
+/// Longest file path in a project submission, checked by the master and
+/// again by the worker that builds it
+pub const MAX_PROJECT_PATH_LEN: usize = 255;
+
+/// Map a user-supplied language name or alias (e.g. "c++", "js") to its canonical name
+pub fn canonical_language(language: &str) -> Option<&'static str> {
+    match language.to_lowercase().as_str() {
+        "c" => Some("c"),
+        "cpp" | "c++" => Some("cpp"),
+        "rust" => Some("rust"),
+        "go" | "golang" => Some("go"),
+        "java" => Some("java"),
+        "python" | "python3" => Some("python"),
+        "javascript" | "js" | "node" => Some("javascript"),
+        "ruby" => Some("ruby"),
+        _ => None,
+    }
+}
+
+/// Whether a submission in `language` (canonical) may pass `flag` to its
+/// compiler. Only exact, known-harmless flags are allowed; languages compiled
+/// without flags allow none.
+pub fn compiler_flag_allowed(language: &str, flag: &str) -> bool {
+    // Optimization, warning and debug flags of gcc and g++
+    let gcc = matches!(
+        flag,
+        "-O0"
+            | "-O1"
+            | "-O2"
+            | "-O3"
+            | "-Os"
+            | "-Og"
+            | "-g"
+            | "-w"
+            | "-Wall"
+            | "-Wextra"
+            | "-lm"
+            | "-pthread"
+            | "-DONLINE_JUDGE"
+    );
+    match language {
+        "c" => {
+            gcc || matches!(
+                flag,
+                "-std=c99" | "-std=c11" | "-std=c17" | "-std=gnu99" | "-std=gnu11" | "-std=gnu17"
+            )
+        }
+        "cpp" => {
+            gcc || matches!(
+                flag,
+                "-std=c++11"
+                    | "-std=c++14"
+                    | "-std=c++17"
+                    | "-std=c++20"
+                    | "-std=c++23"
+                    | "-std=gnu++17"
+                    | "-std=gnu++20"
+            )
+        }
+        "rust" => matches!(
+            flag,
+            "-O" | "-g"
+                | "-Copt-level=0"
+                | "-Copt-level=1"
+                | "-Copt-level=2"
+                | "-Copt-level=3"
+                | "--edition=2015"
+                | "--edition=2018"
+                | "--edition=2021"
+                | "--edition=2024"
+        ),
+        _ => false,
+    }
+}
+
+/// Build commands a project may use instead of its language's default build,
+/// per canonical language, each run as this argv (no shell) in the project root
+const BUILD_COMMANDS: &[(&str, &[&str])] = &[
+    ("c", &["make"]),
+    ("cpp", &["make"]),
+    ("rust", &["cargo", "build", "--offline"]),
+    ("rust", &["cargo", "build", "--offline", "--release"]),
+    ("go", &["go", "build", "-o", "/tmp/main", "."]),
+    ("java", &["javac", "-d", "/tmp/classes", "Main.java"]),
+];
+
+/// The argv of a project's build command in `language` (canonical), if it
+/// is one of the allowed ones; words may be separated by any whitespace
+pub fn build_command_argv(language: &str, command: &str) -> Option<&'static [&'static str]> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    BUILD_COMMANDS
+        .iter()
+        .find(|(lang, argv)| *lang == language && *argv == words.as_slice())
+        .map(|(_, argv)| *argv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_compiler_flags_are_allowed() {
+        for flag in ["-O2", "-Wall", "-lm", "-std=c11"] {
+            assert!(compiler_flag_allowed("c", flag), "{}", flag);
+        }
+        for flag in ["-O3", "-g", "-pthread", "-std=c++20"] {
+            assert!(compiler_flag_allowed("cpp", flag), "{}", flag);
+        }
+        for flag in ["-O", "-Copt-level=2", "--edition=2021"] {
+            assert!(compiler_flag_allowed("rust", flag), "{}", flag);
+        }
+    }
+
+    #[test]
+    fn injected_or_dangerous_flags_are_rejected() {
+        for flag in [
+            "-O2; rm -rf /",
+            "-O2 -o /etc/passwd",
+            "$(id)",
+            "`id`",
+            "-o/tmp/main",
+            "-o",
+            "-fplugin=/tmp/evil.so",
+            "@/etc/passwd",
+            "-O2\n",
+            " -O2",
+            "",
+        ] {
+            for language in ["c", "cpp", "rust"] {
+                assert!(!compiler_flag_allowed(language, flag), "{:?}", flag);
+            }
+        }
+    }
+
+    #[test]
+    fn flags_are_allowed_per_language() {
+        assert!(!compiler_flag_allowed("c", "-std=c++17"));
+        assert!(!compiler_flag_allowed("cpp", "-std=c11"));
+        assert!(!compiler_flag_allowed("rust", "-O2"));
+        assert!(!compiler_flag_allowed("c", "--edition=2021"));
+        for language in ["python", "go", "java", "unknown"] {
+            assert!(!compiler_flag_allowed(language, "-O2"), "{}", language);
+        }
+    }
+
+    #[test]
+    fn build_commands_are_matched_per_language() {
+        assert_eq!(build_command_argv("cpp", "make"), Some(&["make"][..]));
+        assert_eq!(
+            build_command_argv("rust", "  cargo build\t--offline --release\n"),
+            Some(&["cargo", "build", "--offline", "--release"][..])
+        );
+        assert_eq!(build_command_argv("rust", "make"), None);
+        assert_eq!(build_command_argv("python", "make"), None);
+    }
+
+    #[test]
+    fn build_commands_do_not_reach_a_shell() {
+        for command in [
+            "make; curl evil",
+            "make && id",
+            "make $(id)",
+            "make `id`",
+            "make CC=/tmp/evil",
+            "cargo build",
+            "",
+        ] {
+            for language in ["c", "cpp", "rust"] {
+                assert_eq!(build_command_argv(language, command), None, "{:?}", command);
+            }
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! Master Node - Grading
+//!
+//! Turns a job's test results into an overall verdict and a score. Tests may
+//! be grouped into subtasks worth a number of points, each scored under its
+//! own policy; without subtasks every test is worth one point.
+
+use common::scheduler::{TestCase, TestCaseResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Outcome of a job, or of a single test or subtask
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Verdict {
+    #[serde(rename = "AC")]
+    Accepted,
+    #[serde(rename = "WA")]
+    WrongAnswer,
+    #[serde(rename = "TLE")]
+    TimeLimitExceeded,
+    #[serde(rename = "RE")]
+    RuntimeError,
+    #[serde(rename = "MLE")]
+    MemoryLimitExceeded,
+    #[serde(rename = "CE")]
+    CompileError,
+    /// The checker or interactor failed, or a result is missing
+    #[serde(rename = "JE")]
+    JudgeError,
+}
+
+impl Verdict {
+    /// Verdict of a single test from its worker-reported status
+    fn of_test(status: &str) -> Self {
+        match status {
+            "PASSED" => Verdict::Accepted,
+            "FAILED" | "PARTIAL" => Verdict::WrongAnswer,
+            "TLE" => Verdict::TimeLimitExceeded,
+            "RE" => Verdict::RuntimeError,
+            "MLE" => Verdict::MemoryLimitExceeded,
+            _ => Verdict::JudgeError,
+        }
+    }
+}
+
+/// How a subtask's tests add up to its points
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoringPolicy {
+    /// Full points only if every test passes
+    #[default]
+    AllOrNothing,
+    /// Points scaled by the lowest test score
+    Min,
+    /// Points shared equally between the tests, each scaled by its score
+    Sum,
+}
+
+/// A group of tests worth a number of points
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subtask {
+    pub id: String,
+    pub points: f64,
+    pub policy: ScoringPolicy,
+    pub test_ids: Vec<String>,
+}
+
+/// Score of one subtask
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtaskScore {
+    pub id: String,
+    pub verdict: Verdict,
+    pub score: f64,
+    pub points: f64,
+}
+
+/// Overall outcome of a job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grade {
+    pub verdict: Verdict,
+    pub score: f64,
+    pub max_score: f64,
+    /// Per-subtask scores; empty when the job has no subtasks
+    pub subtasks: Vec<SubtaskScore>,
+}
+
+/// Grade a job whose tests have all finished
+///
+/// The verdict is that of the first test (in submission order) that did not
+/// pass, or `Accepted` if they all did.
+pub fn grade(test_cases: &[TestCase], results: &[TestCaseResult], subtasks: &[Subtask]) -> Grade {
+    let by_id: HashMap<&str, &TestCaseResult> =
+        results.iter().map(|r| (r.test_id.as_str(), r)).collect();
+    let outcome = |test_id: &str| match by_id.get(test_id) {
+        Some(r) => (Verdict::of_test(&r.status), test_score(r)),
+        None => (Verdict::JudgeError, 0.0),
+    };
+
+    let verdict = first_failure(test_cases.iter().map(|tc| outcome(&tc.id).0));
+
+    if subtasks.is_empty() {
+        return Grade {
+            verdict,
+            score: total(test_cases.iter().map(|tc| outcome(&tc.id).1)),
+            max_score: test_cases.len() as f64,
+            subtasks: vec![],
+        };
+    }
+
+    let subtasks: Vec<SubtaskScore> = subtasks
+        .iter()
+        .map(|subtask| {
+            let outcomes: Vec<(Verdict, f64)> =
+                subtask.test_ids.iter().map(|id| outcome(id)).collect();
+            let verdict = first_failure(outcomes.iter().map(|(verdict, _)| *verdict));
+            let scores = outcomes.iter().map(|(_, score)| *score);
+
+            let fraction = match subtask.policy {
+                ScoringPolicy::AllOrNothing if verdict == Verdict::Accepted => 1.0,
+                ScoringPolicy::AllOrNothing => 0.0,
+                ScoringPolicy::Min => scores.fold(1.0, f64::min),
+                ScoringPolicy::Sum => total(scores) / outcomes.len().max(1) as f64,
+            };
+
+            SubtaskScore {
+                id: subtask.id.clone(),
+                verdict,
+                score: subtask.points * fraction,
+                points: subtask.points,
+            }
+        })
+        .collect();
+
+    Grade {
+        verdict,
+        score: total(subtasks.iter().map(|s| s.score)),
+        max_score: total(subtasks.iter().map(|s| s.points)),
+        subtasks,
+    }
+}
+
+/// Grade of a job that failed before its tests ran (e.g. compilation failed)
+pub fn failed(verdict: Verdict, test_cases: &[TestCase], subtasks: &[Subtask]) -> Grade {
+    let subtasks: Vec<SubtaskScore> = subtasks
+        .iter()
+        .map(|subtask| SubtaskScore {
+            id: subtask.id.clone(),
+            verdict,
+            score: 0.0,
+            points: subtask.points,
+        })
+        .collect();
+
+    Grade {
+        verdict,
+        score: 0.0,
+        max_score: if subtasks.is_empty() {
+            test_cases.len() as f64
+        } else {
+            total(subtasks.iter().map(|s| s.points))
+        },
+        subtasks,
+    }
+}
+
+/// Score of a test between 0.0 and 1.0; a pass always counts in full
+fn test_score(result: &TestCaseResult) -> f64 {
+    if result.status == "PASSED" {
+        1.0
+    } else {
+        result.score.clamp(0.0, 1.0)
+    }
+}
+
+/// Sum of scores; unlike `Iterator::sum`, 0.0 rather than -0.0 when empty
+fn total(scores: impl Iterator<Item = f64>) -> f64 {
+    scores.fold(0.0, |total, score| total + score)
+}
+
+fn first_failure(mut verdicts: impl Iterator<Item = Verdict>) -> Verdict {
+    verdicts
+        .find(|verdict| *verdict != Verdict::Accepted)
+        .unwrap_or(Verdict::Accepted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tests(ids: &[&str]) -> Vec<TestCase> {
+        ids.iter()
+            .map(|id| TestCase {
+                id: id.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn result(test_id: &str, status: &str, score: f64) -> TestCaseResult {
+        TestCaseResult {
+            test_id: test_id.to_string(),
+            status: status.to_string(),
+            score,
+            ..Default::default()
+        }
+    }
+
+    fn subtask(id: &str, points: f64, policy: ScoringPolicy, test_ids: &[&str]) -> Subtask {
+        Subtask {
+            id: id.to_string(),
+            points,
+            policy,
+            test_ids: test_ids.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn verdict_is_first_failure_in_submission_order() {
+        let test_cases = tests(&["1", "2", "3"]);
+        // Results arrive out of order; the earliest test decides
+        let results = [
+            result("3", "RE", 0.0),
+            result("2", "TLE", 0.0),
+            result("1", "PASSED", 0.0),
+        ];
+        let grade = grade(&test_cases, &results, &[]);
+        assert_eq!(grade.verdict, Verdict::TimeLimitExceeded);
+        assert_eq!(grade.score, 1.0);
+        assert_eq!(grade.max_score, 3.0);
+    }
+
+    #[test]
+    fn missing_or_unknown_results_are_judge_errors() {
+        let test_cases = tests(&["1", "2"]);
+        let missing = grade(&test_cases, &[result("1", "PASSED", 0.0)], &[]);
+        assert_eq!(missing.verdict, Verdict::JudgeError);
+
+        let results = [result("1", "SYSTEM_ERROR", 0.0), result("2", "FAILED", 0.0)];
+        assert_eq!(
+            grade(&test_cases, &results, &[]).verdict,
+            Verdict::JudgeError
+        );
+    }
+
+    #[test]
+    fn all_accepted_without_subtasks() {
+        let test_cases = tests(&["1", "2"]);
+        let results = [result("1", "PASSED", 0.0), result("2", "PASSED", 0.0)];
+        let grade = grade(&test_cases, &results, &[]);
+        assert_eq!(grade.verdict, Verdict::Accepted);
+        assert_eq!(grade.score, 2.0);
+        assert!(grade.subtasks.is_empty());
+    }
+
+    #[test]
+    fn partial_scores_are_wrong_answers_and_clamped() {
+        let test_cases = tests(&["1", "2"]);
+        let results = [result("1", "PARTIAL", 0.5), result("2", "PARTIAL", 3.0)];
+        let grade = grade(&test_cases, &results, &[]);
+        assert_eq!(grade.verdict, Verdict::WrongAnswer);
+        assert_eq!(grade.score, 1.5);
+    }
+
+    #[test]
+    fn subtask_policies() {
+        let test_cases = tests(&["1", "2", "3", "4"]);
+        let results = [
+            result("1", "PASSED", 0.0),
+            result("2", "PARTIAL", 0.5),
+            result("3", "PASSED", 0.0),
+            result("4", "PARTIAL", 0.25),
+        ];
+        let subtasks = [
+            subtask("all", 10.0, ScoringPolicy::AllOrNothing, &["1", "2"]),
+            subtask("all_passed", 5.0, ScoringPolicy::AllOrNothing, &["1", "3"]),
+            subtask("min", 20.0, ScoringPolicy::Min, &["2", "4"]),
+            subtask("sum", 40.0, ScoringPolicy::Sum, &["1", "2", "3", "4"]),
+        ];
+        let grade = grade(&test_cases, &results, &subtasks);
+
+        let scores: Vec<(Verdict, f64)> = grade
+            .subtasks
+            .iter()
+            .map(|s| (s.verdict, s.score))
+            .collect();
+        assert_eq!(
+            scores,
+            [
+                (Verdict::WrongAnswer, 0.0),
+                (Verdict::Accepted, 5.0),
+                (Verdict::WrongAnswer, 5.0),
+                (Verdict::WrongAnswer, 27.5),
+            ]
+        );
+        assert_eq!(grade.verdict, Verdict::WrongAnswer);
+        assert_eq!(grade.score, 37.5);
+        assert_eq!(grade.max_score, 75.0);
+    }
+
+    #[test]
+    fn tests_outside_subtasks_decide_the_verdict_but_score_nothing() {
+        let test_cases = tests(&["sample", "1"]);
+        let results = [result("sample", "FAILED", 0.0), result("1", "PASSED", 0.0)];
+        let subtasks = [subtask("main", 100.0, ScoringPolicy::AllOrNothing, &["1"])];
+        let grade = grade(&test_cases, &results, &subtasks);
+        assert_eq!(grade.verdict, Verdict::WrongAnswer);
+        assert_eq!(grade.score, 100.0);
+        assert_eq!(grade.max_score, 100.0);
+        assert_eq!(grade.subtasks[0].verdict, Verdict::Accepted);
+    }
+
+    #[test]
+    fn failed_jobs_score_nothing() {
+        let test_cases = tests(&["1", "2"]);
+        let grade = failed(Verdict::CompileError, &test_cases, &[]);
+        assert_eq!((grade.score, grade.max_score), (0.0, 2.0));
+
+        let subtasks = [subtask("main", 30.0, ScoringPolicy::Sum, &["1", "2"])];
+        let grade = failed(Verdict::CompileError, &test_cases, &subtasks);
+        assert_eq!((grade.score, grade.max_score), (0.0, 30.0));
+        assert_eq!(grade.subtasks[0].verdict, Verdict::CompileError);
+    }
+}
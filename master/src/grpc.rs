@@ -2,11 +2,16 @@
 //!
 //! Handles bidirectional streaming connections from workers.
 
-use crate::state::{AppState, FinalResponse, JobState, WorkerInfo};
+use crate::grading::Verdict;
+use crate::state::{AppState, AssignedTask, JobEvent, JobState, WorkerInfo, WorkerLiveness};
+use crate::{scheduler, suites};
 use common::scheduler::{
-    worker_message::Payload, worker_service_server::WorkerService, MasterCommand, WorkerMessage,
+    worker_message::Payload, worker_service_server::WorkerService, MasterCommand, SuiteData,
+    SuiteRequest, WorkerMessage,
 };
+use std::collections::HashMap;
 use std::pin::Pin;
+use std::time::Instant;
 use tokio::sync::mpsc;
 use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tonic::{Request, Response, Status, Streaming};
@@ -51,25 +56,53 @@ impl WorkerService for WorkerServiceImpl {
                                         worker_id = %reg.worker_id,
                                         cpu_cores = reg.cpu_cores,
                                         ram_mb = reg.total_ram_mb,
+                                        slots = reg.execution_slots,
                                         tags = ?reg.tags,
+                                        compile_languages = ?reg.compile_languages,
+                                        run_languages = ?reg.run_languages,
                                         "Worker registered"
                                     );
 
                                     worker_id = Some(reg.worker_id.clone());
 
                                     // Store worker info
-                                    state.workers.insert(
+                                    let previous = state.workers.insert(
                                         reg.worker_id.clone(),
                                         WorkerInfo {
                                             sender: tx.clone(),
                                             cpu_cores: reg.cpu_cores,
                                             total_ram_mb: reg.total_ram_mb,
                                             tags: reg.tags,
+                                            compile_languages: reg
+                                                .compile_languages
+                                                .into_iter()
+                                                .collect(),
+                                            run_languages: reg.run_languages.into_iter().collect(),
                                             cpu_load_percent: 0.0,
                                             ram_usage_mb: 0,
                                             active_tasks: 0,
+                                            pools: vec![],
+                                            execution_slots: if reg.execution_slots > 0 {
+                                                reg.execution_slots
+                                            } else {
+                                                reg.cpu_cores.max(1)
+                                            },
+                                            assigned: HashMap::new(),
+                                            last_heartbeat: Instant::now(),
+                                            liveness: WorkerLiveness::Alive,
                                         },
                                     );
+
+                                    // A reconnecting worker has lost whatever its old stream was running
+                                    if let Some(previous) = previous {
+                                        scheduler::requeue_tasks(
+                                            &state,
+                                            &reg.worker_id,
+                                            previous.assigned,
+                                        );
+                                    }
+
+                                    state.wake_dispatcher();
                                 }
 
                                 Payload::Heartbeat(hb) => {
@@ -81,11 +114,20 @@ impl WorkerService for WorkerServiceImpl {
                                         "Heartbeat received"
                                     );
 
-                                    // Update worker metrics
+                                    // Update worker metrics and liveness
                                     if let Some(mut worker) = state.workers.get_mut(&hb.worker_id) {
                                         worker.cpu_load_percent = hb.cpu_load_percent;
                                         worker.ram_usage_mb = hb.ram_usage_mb;
                                         worker.active_tasks = hb.active_tasks;
+                                        worker.pools = hb.pools;
+                                        worker.last_heartbeat = Instant::now();
+
+                                        if worker.liveness != WorkerLiveness::Alive {
+                                            info!(worker_id = %hb.worker_id, "Worker is alive again");
+                                            worker.liveness = WorkerLiveness::Alive;
+                                            drop(worker);
+                                            state.wake_dispatcher();
+                                        }
                                     }
                                 }
 
@@ -93,11 +135,23 @@ impl WorkerService for WorkerServiceImpl {
                                     info!(
                                         job_id = %result.job_id,
                                         success = result.success,
+                                        checker = result.checker,
                                         duration_ms = result.duration_ms,
                                         "Compile result received"
                                     );
 
+                                    if let Some(id) = &worker_id {
+                                        let job_id = result.job_id.clone();
+                                        let task = if result.checker {
+                                            AssignedTask::CheckerCompile { job_id }
+                                        } else {
+                                            AssignedTask::Compile { job_id }
+                                        };
+                                        scheduler::release_slot(&state, id, &task);
+                                    }
+
                                     handle_compile_result(&state, result).await;
+                                    state.wake_dispatcher();
                                 }
 
                                 Payload::BatchResult(result) => {
@@ -108,7 +162,23 @@ impl WorkerService for WorkerServiceImpl {
                                         "Batch execution result received"
                                     );
 
+                                    if let Some(id) = &worker_id {
+                                        scheduler::release_slot(
+                                            &state,
+                                            id,
+                                            &AssignedTask::Batch {
+                                                job_id: result.job_id.clone(),
+                                                batch_id: result.batch_id.clone(),
+                                            },
+                                        );
+                                    }
+
                                     handle_batch_result(&state, result).await;
+                                    state.wake_dispatcher();
+                                }
+
+                                Payload::TestResult(progress) => {
+                                    handle_test_result(&state, progress);
                                 }
                             }
                         }
@@ -120,10 +190,17 @@ impl WorkerService for WorkerServiceImpl {
                 }
             }
 
-            // Worker disconnected - clean up
+            // Worker disconnected - clean up and re-dispatch its in-flight work.
+            // Only remove the entry if it still belongs to this stream; the worker
+            // may already have re-registered on a new one.
             if let Some(id) = worker_id {
                 info!(worker_id = %id, "Worker disconnected");
-                state.workers.remove(&id);
+                if let Some((_, worker)) = state
+                    .workers
+                    .remove_if(&id, |_, worker| worker.sender.same_channel(&tx))
+                {
+                    scheduler::requeue_tasks(&state, &id, worker.assigned);
+                }
             }
         });
 
@@ -131,87 +208,139 @@ impl WorkerService for WorkerServiceImpl {
         let output_stream = ReceiverStream::new(rx);
         Ok(Response::new(Box::pin(output_stream)))
     }
+
+    async fn get_suite(
+        &self,
+        request: Request<SuiteRequest>,
+    ) -> Result<Response<SuiteData>, Status> {
+        let hash = request.into_inner().hash;
+        let suite = suites::find_by_hash(&self.state, &hash)
+            .ok_or_else(|| Status::not_found(format!("Unknown suite {}", hash)))?;
+
+        info!(suite = %suite.reference(), hash = %hash, "Sending suite to worker");
+        Ok(Response::new(SuiteData {
+            hash,
+            test_cases: suite.test_cases.to_vec(),
+        }))
+    }
 }
 
 async fn handle_compile_result(state: &AppState, result: common::scheduler::CompileResult) {
+    if result.checker {
+        handle_checker_compile_result(state, result);
+        return;
+    }
+
     let job_id = result.job_id.clone();
-    
+
     // First, update the job with compile result
-    let dispatch_info = {
+    let compiled = {
         if let Some(mut job) = state.jobs.get_mut(&job_id) {
+            if !matches!(job.state, JobState::Compiling) {
+                warn!(job_id = %job_id, "Ignoring compile result for job that is not compiling");
+                return;
+            }
+
             job.compiler_output = Some(result.compiler_output.clone());
+            job.emit(JobEvent::CompileOutput {
+                success: result.success,
+                output: result.compiler_output,
+            });
 
-            if result.success {
-                job.binary = Some(result.binary_payload.clone());
-                job.state = JobState::Executing { pending_batches: 1 };
-                
-                // Gather info needed for dispatch
-                Some((
-                    job.language.clone(),
-                    result.binary_payload.clone(),
-                    job.test_cases.clone(),
-                    job.time_limit_ms,
-                    job.memory_limit_mb,
-                ))
+            let compiled = if result.success {
+                state.persist_binary(&job_id, false, result.binary_payload.clone());
+                job.binary = Some(result.binary_payload);
+                scheduler::prepare_execution(&mut job);
+                true
             } else {
                 // Compilation failed - complete the job with error
-                job.state = JobState::Completed;
-                None
-            }
+                job.fail(Verdict::CompileError, "Compilation failed".to_string());
+                false
+            };
+            state.persist(&job);
+            compiled
         } else {
-            None
+            false
         }
     };
 
-    // Dispatch execution if compilation succeeded
-    if let Some((language, binary, test_cases, time_limit, memory_limit)) = dispatch_info {
-        info!(job_id = %job_id, "Compilation successful, dispatching execution phase");
-
-        // Find a worker to execute
-        let worker_id = state
-            .workers
-            .iter()
-            .min_by(|a, b| {
-                a.value()
-                    .cpu_load_percent
-                    .partial_cmp(&b.value().cpu_load_percent)
-                    .unwrap_or(std::cmp::Ordering::Equal)
-            })
-            .map(|entry| entry.key().clone());
-
-        if let Some(worker_id) = worker_id {
-            let task = common::scheduler::ExecuteBatchTask {
-                job_id: job_id.clone(),
-                batch_id: "batch_1".to_string(),
-                language,
-                payload: Some(common::scheduler::execute_batch_task::Payload::BinaryArtifact(binary)),
-                inputs: test_cases,
-                time_limit_ms: time_limit,
-                memory_limit_mb: memory_limit,
-            };
+    // Queue the execution phase if compilation succeeded; the job goes to the
+    // front of the queue since it has already been started
+    if compiled {
+        info!(job_id = %job_id, "Compilation successful, queueing execution phase");
+        state.enqueue_front(&job_id);
+    } else {
+        info!(job_id = %job_id, "Compilation failed");
+    }
+}
 
-            let cmd = MasterCommand {
-                task: Some(common::scheduler::master_command::Task::Execute(task)),
-            };
+/// Keep a compiled checker and queue the job again to compile its submission
+/// (or go straight to execution); a checker that fails to build fails the job
+fn handle_checker_compile_result(state: &AppState, result: common::scheduler::CompileResult) {
+    let Some(mut job) = state.jobs.get_mut(&result.job_id) else {
+        return;
+    };
+    if !matches!(job.state, JobState::Compiling) || job.checker_binary.is_some() {
+        warn!(job_id = %result.job_id, "Ignoring unexpected checker compile result");
+        return;
+    }
 
-            if let Some(worker) = state.workers.get(&worker_id) {
-                let _ = worker.sender.send(Ok(cmd)).await;
-                info!(job_id = %job_id, worker_id = %worker_id, "Dispatched execute task with binary");
-            }
-        } else {
-            warn!(job_id = %job_id, "No workers available for execution phase");
-        }
+    if result.success {
+        state.persist_binary(&result.job_id, true, result.binary_payload.clone());
+        job.checker_binary = Some(result.binary_payload);
+        job.set_state(JobState::Queued);
+        state.persist(&job);
+        drop(job);
+
+        info!(job_id = %result.job_id, "Checker compiled");
+        state.enqueue_front(&result.job_id);
     } else {
-        info!(job_id = %job_id, "Compilation failed");
+        let kind = match &job.checker {
+            Some(checker) if checker.interactive => "Interactor",
+            _ => "Checker",
+        };
+        job.fail(
+            Verdict::JudgeError,
+            format!("{} compilation failed: {}", kind, result.compiler_output),
+        );
+        state.persist(&job);
+
+        info!(job_id = %result.job_id, "Checker compilation failed");
+    }
+}
+
+fn handle_test_result(state: &AppState, progress: common::scheduler::TestCaseProgress) {
+    let Some(result) = progress.result else {
+        return;
+    };
+
+    if let Some(mut job) = state.jobs.get_mut(&progress.job_id) {
+        if !matches!(job.state, JobState::Executing { .. }) {
+            return;
+        }
+
+        if !job.record_live_result(&progress.batch_id, result) {
+            warn!(
+                job_id = %progress.job_id,
+                batch_id = %progress.batch_id,
+                "Ignoring test result for unexpected batch"
+            );
+        }
     }
 }
 
 async fn handle_batch_result(state: &AppState, result: common::scheduler::BatchExecutionResult) {
     if let Some(mut job) = state.jobs.get_mut(&result.job_id) {
-        // Append results
-        job.results.extend(result.results);
+        if !matches!(job.state, JobState::Executing { .. }) {
+            warn!(
+                job_id = %result.job_id,
+                batch_id = %result.batch_id,
+                "Ignoring batch result for job that is not executing"
+            );
+            return;
+        }
 
-        // Check if this was a system error
+        // The batch did not run; its results, if any, are not a verdict
         if !result.system_error.is_empty() {
             warn!(
                 job_id = %result.job_id,
@@ -219,26 +348,93 @@ async fn handle_batch_result(state: &AppState, result: common::scheduler::BatchE
                 error = %result.system_error,
                 "Batch execution had system error"
             );
+            drop(job);
+            scheduler::retry_failed_batch(
+                state,
+                &result.job_id,
+                &result.batch_id,
+                &result.system_error,
+            );
+            return;
+        }
+
+        // Store results; ignore batches we don't know or have already seen
+        if !job.record_batch(&result.batch_id, result.results) {
+            warn!(
+                job_id = %result.job_id,
+                batch_id = %result.batch_id,
+                "Ignoring unexpected batch result"
+            );
+            return;
+        }
+        if let Some(results) = job.batch_results.get(&result.batch_id) {
+            state.persist_batch(&result.job_id, &result.batch_id, results);
         }
 
         // Decrement pending batches
-        if let JobState::Executing { pending_batches } = &mut job.state {
-            *pending_batches = pending_batches.saturating_sub(1);
+        if let JobState::Executing { pending_batches } = job.state {
+            let pending_batches = pending_batches.saturating_sub(1);
 
-            if *pending_batches == 0 {
+            if pending_batches == 0 {
                 // All batches complete
-                job.state = JobState::Completed;
-
-                if let Some(responder) = job.responder.take() {
-                    let _ = responder.send(FinalResponse {
-                        job_id: result.job_id,
-                        success: true,
-                        results: job.results.clone(),
-                        compiler_output: job.compiler_output.clone(),
-                        error: None,
-                    });
-                }
+                job.complete();
+                state.persist(&job);
+            } else {
+                job.set_state(JobState::Executing { pending_batches });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::JobContext;
+    use crate::store::SqliteJobStore;
+    use common::scheduler::{BatchExecutionResult, TestCase, TestCaseResult};
+    use std::sync::Arc;
+
+    /// Subscribers see the same progress between batches as `/status`
+    #[tokio::test]
+    async fn finished_batch_emits_state_event() {
+        let state = AppState::new(Arc::new(SqliteJobStore::open(":memory:").unwrap()));
+        let test_cases: Vec<TestCase> = (0..21)
+            .map(|i| TestCase {
+                id: i.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let mut job = JobContext::new(
+            "job".to_string(),
+            "python".to_string(),
+            "print(1)".to_string(),
+            vec![],
+            Arc::new(test_cases),
+            1000,
+            128,
+        );
+        scheduler::prepare_execution(&mut job);
+        let mut events = job.events.subscribe();
+        state.jobs.insert("job".to_string(), job);
+
+        let result = BatchExecutionResult {
+            job_id: "job".to_string(),
+            batch_id: scheduler::batch_id(1),
+            results: vec![TestCaseResult {
+                test_id: "20".to_string(),
+                status: "PASSED".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        handle_batch_result(&state, result).await;
+
+        let mut pending = None;
+        while let Ok(event) = events.try_recv() {
+            if let JobEvent::State(JobState::Executing { pending_batches }) = event {
+                pending = Some(pending_batches);
             }
         }
+        assert_eq!(pending, Some(1));
     }
 }
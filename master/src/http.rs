@@ -2,32 +2,94 @@
 //!
 //! Provides REST API for clients to submit code and check job status.
 
-use crate::state::{AppState, FinalResponse, JobContext, JobState};
+use crate::grading::{ScoringPolicy, Subtask, SubtaskScore, Verdict};
+use crate::project::{self, Project};
+use crate::scheduler;
+use crate::state::{
+    AppState, CheckerSource, FinalResponse, JobContext, JobEvent, JobState, WebhookDelivery,
+};
+use crate::suites::{self, Suite};
+use crate::webhook::{self, Webhook};
 use axum::{
-    extract::{Path, State},
+    extract::{DefaultBodyLimit, Path, Query, State},
     http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post},
     Json, Router,
 };
-use common::scheduler::TestCaseResult;
+use base64::Engine;
+use common::scheduler::{
+    comparator, execute_batch_task::Isolation, Comparator, SourceFile, TestCase, TestCaseResult,
+};
 use serde::{Deserialize, Serialize};
-use tokio::sync::oneshot;
-use tracing::info;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 /// Request body for code submission
 #[derive(Debug, Deserialize)]
 pub struct SubmitRequest {
     pub language: String,
+    #[serde(default)]
     pub source_code: String,
+    /// Project submissions: the source tree, instead of `source_code`
+    #[serde(default)]
+    pub files: Vec<SourceFileInput>,
+    /// Project submissions: the source tree as a base64-encoded tar archive
+    #[serde(default)]
+    pub archive: Option<String>,
+    /// Project submissions: command that builds the tree instead of the
+    /// default build, one of the language's `common::build_command_argv`
+    /// (e.g. `make`)
+    #[serde(default)]
+    pub build_command: Option<String>,
+    /// Project submissions: path of the built binary (for Java, the class
+    /// directory), relative to the project root; or where the default build
+    /// leaves it, `/tmp/main` (`/tmp/classes` for Java)
+    #[serde(default)]
+    pub artifact: Option<String>,
+    #[serde(default)]
     pub test_cases: Vec<TestCaseInput>,
+    /// Run the tests of an uploaded suite (`name@version`, or `name` for the
+    /// latest version) instead of `test_cases`
+    #[serde(default)]
+    pub suite: Option<String>,
+    /// Each must be on the language's allow-list (`common::compiler_flag_allowed`)
     #[serde(default)]
     pub compiler_flags: Vec<String>,
+    /// How outputs are compared; trimmed equality if absent
+    #[serde(default)]
+    pub comparator: Option<ComparatorInput>,
+    /// Program that judges outputs instead of the comparators
+    #[serde(default)]
+    pub checker: Option<CheckerInput>,
+    /// Program the submission talks to over stdin/stdout (interactive problems)
+    #[serde(default)]
+    pub interactor: Option<CheckerInput>,
+    /// Groups of tests worth points; every test is worth one point if empty
+    #[serde(default)]
+    pub subtasks: Vec<SubtaskInput>,
     #[serde(default = "default_time_limit")]
     pub time_limit_ms: u32,
     #[serde(default = "default_memory_limit")]
     pub memory_limit_mb: u32,
+    /// Whether each test runs in a fresh sandbox; `shared` if absent
+    #[serde(default)]
+    pub isolation: IsolationMode,
+    /// URL to POST the final status to once the job completes
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    /// Key used to sign the callback payload (HMAC-SHA256)
+    #[serde(default)]
+    pub callback_secret: Option<String>,
 }
 
 fn default_time_limit() -> u32 {
@@ -38,11 +100,167 @@ fn default_memory_limit() -> u32 {
     128 // 128 MB
 }
 
+/// How the tests of a job share sandboxes
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IsolationMode {
+    /// A batch of tests runs in one container (the fast mode)
+    #[default]
+    Shared,
+    /// Every test runs in a fresh container with its own memory limit, so
+    /// nothing one test writes, starts or allocates reaches the next
+    PerTest,
+}
+
+impl IsolationMode {
+    fn to_proto(self) -> Isolation {
+        match self {
+            IsolationMode::Shared => Isolation::Shared,
+            IsolationMode::PerTest => Isolation::PerTest,
+        }
+    }
+}
+
+/// Bounds on a job's time limit per test
+const MIN_TIME_LIMIT_MS: u32 = 1;
+const MAX_TIME_LIMIT_MS: u32 = 60_000;
+
+/// Bounds on a job's memory limit per test (Docker refuses less than 6 MB)
+const MIN_MEMORY_LIMIT_MB: u32 = 16;
+const MAX_MEMORY_LIMIT_MB: u32 = 16_384;
+
+/// Upper bound on how long a blocking submission may wait
+const MAX_WAIT_TIMEOUT_MS: u64 = 300_000;
+
+/// Query parameters for code submission
+#[derive(Debug, Deserialize)]
+pub struct SubmitParams {
+    /// Hold the request open until the job finishes
+    #[serde(default)]
+    pub wait: bool,
+    /// How long to wait before answering 202 instead (capped at `MAX_WAIT_TIMEOUT_MS`)
+    #[serde(default = "default_wait_timeout")]
+    pub timeout_ms: u64,
+}
+
+fn default_wait_timeout() -> u64 {
+    30_000 // 30 seconds
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TestCaseInput {
     pub id: String,
+    #[serde(default)]
     pub input: String,
+    /// Raw stdin bytes, base64-encoded, instead of `input`
+    #[serde(default)]
+    pub input_base64: Option<String>,
     pub expected_output: String,
+    /// Overrides the job's comparator for this test
+    #[serde(default)]
+    pub comparator: Option<ComparatorInput>,
+}
+
+/// One file of a project submission
+#[derive(Debug, Deserialize)]
+pub struct SourceFileInput {
+    /// Relative to the project root, e.g. `src/util.h`
+    pub path: String,
+    pub content: String,
+}
+
+/// Request body for uploading a test suite
+#[derive(Debug, Deserialize)]
+pub struct SuiteRequest {
+    pub name: String,
+    pub test_cases: Vec<TestCaseInput>,
+}
+
+/// A group of tests worth `points`, scored under `policy`
+#[derive(Debug, Deserialize)]
+pub struct SubtaskInput {
+    pub id: String,
+    pub points: f64,
+    /// `all_or_nothing` (the default), `min` or `sum`
+    #[serde(default)]
+    pub policy: ScoringPolicy,
+    pub test_ids: Vec<String>,
+}
+
+/// Checker program (special judge), compiled once and run after every test
+/// with the test's input, the submission's output and the expected output.
+/// Also describes an interactor, which runs alongside the submission instead.
+#[derive(Debug, Deserialize)]
+pub struct CheckerInput {
+    /// A compiled language
+    pub language: String,
+    pub source_code: String,
+    #[serde(default)]
+    pub compiler_flags: Vec<String>,
+    /// How long it may run per test
+    #[serde(default = "default_checker_time_limit")]
+    pub time_limit_ms: u32,
+}
+
+fn default_checker_time_limit() -> u32 {
+    10_000 // 10 seconds
+}
+
+/// How a test's stdout is checked against its expected output
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ComparatorInput {
+    pub mode: ComparatorMode,
+    /// Absolute tolerance for `float` mode
+    #[serde(default = "default_epsilon")]
+    pub abs_epsilon: f64,
+    /// Relative tolerance for `float` mode
+    #[serde(default = "default_epsilon")]
+    pub rel_epsilon: f64,
+}
+
+fn default_epsilon() -> f64 {
+    1e-6
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComparatorMode {
+    /// Equal after trimming surrounding whitespace (the default)
+    Trim,
+    /// Byte-for-byte equal
+    Exact,
+    /// Equal line by line, ignoring whitespace around each line
+    Lines,
+    /// Equal whitespace-separated tokens
+    Tokens,
+    /// Like `trim`, ignoring letter case
+    CaseInsensitive,
+    /// Like `tokens`, with numbers equal within the epsilons
+    Float,
+}
+
+impl ComparatorInput {
+    fn to_proto(self) -> Result<Comparator, String> {
+        let valid = |epsilon: f64| epsilon.is_finite() && epsilon >= 0.0;
+        if !valid(self.abs_epsilon) || !valid(self.rel_epsilon) {
+            return Err("Comparator epsilons must be finite and non-negative".to_string());
+        }
+
+        let mode = match self.mode {
+            ComparatorMode::Trim => comparator::Mode::Trim,
+            ComparatorMode::Exact => comparator::Mode::Exact,
+            ComparatorMode::Lines => comparator::Mode::Lines,
+            ComparatorMode::Tokens => comparator::Mode::Tokens,
+            ComparatorMode::CaseInsensitive => comparator::Mode::CaseInsensitive,
+            ComparatorMode::Float => comparator::Mode::Float,
+        };
+
+        Ok(Comparator {
+            mode: mode as i32,
+            abs_epsilon: self.abs_epsilon,
+            rel_epsilon: self.rel_epsilon,
+        })
+    }
 }
 
 /// Response for job submission
@@ -50,6 +268,8 @@ pub struct TestCaseInput {
 pub struct SubmitResponse {
     pub job_id: String,
     pub message: String,
+    /// 1-based position in the master queue at submission time
+    pub queue_position: Option<usize>,
 }
 
 /// Response for job status
@@ -57,9 +277,56 @@ pub struct SubmitResponse {
 pub struct StatusResponse {
     pub job_id: String,
     pub state: String,
+    /// 1-based position in the master queue while the job is queued
+    pub queue_position: Option<usize>,
+    /// Whether the job succeeded, once it has finished
+    pub success: Option<bool>,
+    pub total_test_cases: usize,
     pub results: Vec<TestResultOutput>,
     pub compiler_output: Option<String>,
     pub error: Option<String>,
+    /// Overall verdict (AC, WA, TLE, RE, MLE, CE or JE), once the job has been graded
+    pub verdict: Option<Verdict>,
+    pub score: Option<f64>,
+    pub max_score: Option<f64>,
+    /// Per-subtask scores, if the job has subtasks
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subtasks: Vec<SubtaskScore>,
+    /// Completion webhook delivery attempts, if a callback was requested
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub webhook_deliveries: Vec<WebhookDelivery>,
+}
+
+/// Response for suite upload and lookup
+#[derive(Debug, Serialize)]
+pub struct SuiteResponse {
+    pub suite_id: String,
+    pub version: u32,
+    /// `suite_id@version`, for use in submissions
+    pub reference: String,
+    /// Content hash of the test cases
+    pub hash: String,
+    pub total_test_cases: usize,
+}
+
+impl From<&Suite> for SuiteResponse {
+    fn from(suite: &Suite) -> Self {
+        Self {
+            suite_id: suite.name.clone(),
+            version: suite.version,
+            reference: suite.reference(),
+            hash: suite.hash.clone(),
+            total_test_cases: suite.test_cases.len(),
+        }
+    }
+}
+
+/// Response for job cancellation
+#[derive(Debug, Serialize)]
+pub struct CancelResponse {
+    pub job_id: String,
+    pub state: String,
+    pub message: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -68,8 +335,17 @@ pub struct TestResultOutput {
     pub status: String,
     pub time_ms: i32,
     pub memory_bytes: i32,
+    /// Stdout as text; bytes that are not valid UTF-8 are replaced
     pub stdout: String,
+    /// Exact stdout, base64-encoded, when it is not valid UTF-8
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout_base64: Option<String>,
     pub stderr: String,
+    /// 1.0 for a pass, 0.0 for a failure, in between for partial credit
+    pub score: f64,
+    /// What the job's checker printed about this test
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checker_message: Option<String>,
 }
 
 impl From<TestCaseResult> for TestResultOutput {
@@ -79,9 +355,120 @@ impl From<TestCaseResult> for TestResultOutput {
             status: r.status,
             time_ms: r.time_ms,
             memory_bytes: r.memory_bytes,
-            stdout: r.stdout,
+            stdout: String::from_utf8_lossy(&r.stdout).into_owned(),
+            stdout_base64: std::str::from_utf8(&r.stdout)
+                .is_err()
+                .then(|| base64::engine::general_purpose::STANDARD.encode(&r.stdout)),
             stderr: r.stderr,
+            score: r.score,
+            checker_message: Some(r.checker_message).filter(|m| !m.is_empty()),
+        }
+    }
+}
+
+impl From<FinalResponse> for StatusResponse {
+    fn from(r: FinalResponse) -> Self {
+        Self {
+            job_id: r.job_id,
+            state: state_str(&r.state).to_string(),
+            queue_position: None,
+            success: Some(r.success),
+            total_test_cases: r.total_test_cases,
+            results: r.results.into_iter().map(Into::into).collect(),
+            compiler_output: r.compiler_output,
+            error: r.error,
+            verdict: r.grade.as_ref().map(|g| g.verdict),
+            score: r.grade.as_ref().map(|g| g.score),
+            max_score: r.grade.as_ref().map(|g| g.max_score),
+            subtasks: r.grade.map(|g| g.subtasks).unwrap_or_default(),
+            webhook_deliveries: vec![],
+        }
+    }
+}
+
+impl StatusResponse {
+    /// Current status of a job
+    pub fn for_job(state: &AppState, job: &JobContext) -> Self {
+        let success = match job.state {
+            JobState::Completed => Some(
+                job.error.is_none()
+                    && job
+                        .grade
+                        .as_ref()
+                        .is_none_or(|g| g.verdict == Verdict::Accepted),
+            ),
+            JobState::Cancelled => Some(false),
+            _ => None,
+        };
+
+        Self {
+            job_id: job.id.clone(),
+            state: state_str(&job.state).to_string(),
+            queue_position: if job.is_finished() {
+                None
+            } else {
+                state.queue_position(&job.id)
+            },
+            success,
+            total_test_cases: job.total_test_cases,
+            results: job.results.iter().cloned().map(Into::into).collect(),
+            compiler_output: job.compiler_output.clone(),
+            error: job.error.clone(),
+            verdict: job.grade.as_ref().map(|g| g.verdict),
+            score: job.grade.as_ref().map(|g| g.score),
+            max_score: job.grade.as_ref().map(|g| g.max_score),
+            subtasks: job
+                .grade
+                .as_ref()
+                .map(|g| g.subtasks.clone())
+                .unwrap_or_default(),
+            webhook_deliveries: job.webhook_deliveries.clone(),
+        }
+    }
+
+    /// Status of a job that is not in memory: expired by retention, or unknown
+    fn missing(state: &AppState, job_id: String) -> (StatusCode, Self) {
+        let (code, job_state, error) = if state.is_expired(&job_id) {
+            (StatusCode::GONE, "expired", "Job results expired")
+        } else {
+            (StatusCode::NOT_FOUND, "not_found", "Job not found")
+        };
+
+        (
+            code,
+            Self {
+                job_id,
+                state: job_state.to_string(),
+                queue_position: None,
+                success: None,
+                total_test_cases: 0,
+                results: vec![],
+                compiler_output: None,
+                error: Some(error.to_string()),
+                verdict: None,
+                score: None,
+                max_score: None,
+                subtasks: vec![],
+                webhook_deliveries: vec![],
+            },
+        )
+    }
+}
+
+/// Client-facing name of a job state
+fn state_str(state: &JobState) -> &'static str {
+    match state {
+        JobState::Queued => "queued",
+        JobState::Compiling => "compiling",
+        JobState::Executing { pending_batches } => {
+            if *pending_batches > 0 {
+                "executing"
+            } else {
+                "completed"
+            }
         }
+        JobState::Completed => "completed",
+        JobState::Cancelled => "cancelled",
     }
 }
 
@@ -89,9 +476,19 @@ impl From<TestCaseResult> for TestResultOutput {
 pub fn create_router(state: AppState) -> Router {
     Router::new()
         .route("/health", get(health_check))
-        .route("/submit", post(submit_job))
+        .route(
+            "/submit",
+            post(submit_job).layer(DefaultBodyLimit::max(project::MAX_REQUEST_BYTES)),
+        )
         .route("/status/:job_id", get(get_job_status))
+        .route("/jobs/:job_id", delete(cancel_job))
+        .route("/jobs/:job_id/events", get(job_events))
         .route("/workers", get(list_workers))
+        .route(
+            "/suites",
+            post(create_suite).layer(DefaultBodyLimit::max(suites::MAX_UPLOAD_BYTES)),
+        )
+        .route("/suites/:reference", get(get_suite))
         .with_state(state)
 }
 
@@ -101,190 +498,596 @@ async fn health_check() -> impl IntoResponse {
 
 async fn submit_job(
     State(state): State<AppState>,
+    Query(params): Query<SubmitParams>,
     Json(req): Json<SubmitRequest>,
-) -> impl IntoResponse {
-    let job_id = Uuid::new_v4().to_string();
+) -> Response {
+    let Some(language) = common::canonical_language(&req.language) else {
+        return submit_error(
+            StatusCode::BAD_REQUEST,
+            format!("Unsupported language: {}", req.language),
+        );
+    };
 
-    info!(
-        job_id = %job_id,
-        language = %req.language,
-        test_cases = req.test_cases.len(),
-        "Job submitted"
-    );
+    // With no workers connected the job simply waits; otherwise refuse work
+    // that none of the connected workers can run
+    if !state.workers.is_empty() && !scheduler::is_language_supported(&state, language) {
+        return submit_error(
+            StatusCode::BAD_REQUEST,
+            format!("No connected worker supports language: {}", language),
+        );
+    }
 
-    // Check if we have any available workers
-    if state.workers.is_empty() {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(SubmitResponse {
-                job_id: job_id.clone(),
-                message: "No workers available".to_string(),
-            }),
+    if !(MIN_TIME_LIMIT_MS..=MAX_TIME_LIMIT_MS).contains(&req.time_limit_ms) {
+        return submit_error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "time_limit_ms must be between {} and {}",
+                MIN_TIME_LIMIT_MS, MAX_TIME_LIMIT_MS
+            ),
+        );
+    }
+    if !(MIN_MEMORY_LIMIT_MB..=MAX_MEMORY_LIMIT_MB).contains(&req.memory_limit_mb) {
+        return submit_error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "memory_limit_mb must be between {} and {}",
+                MIN_MEMORY_LIMIT_MB, MAX_MEMORY_LIMIT_MB
+            ),
         );
     }
 
-    // Determine if language is interpreted or compiled
-    let is_interpreted = matches!(
-        req.language.to_lowercase().as_str(),
-        "python" | "python3" | "javascript" | "js" | "node" | "ruby"
-    );
+    if let Some(flag) = disallowed_flag(language, &req.compiler_flags) {
+        return submit_error(
+            StatusCode::BAD_REQUEST,
+            format!("Compiler flag not allowed for {}: {}", language, flag),
+        );
+    }
 
-    // Convert test cases to protobuf format
-    let proto_test_cases: Vec<common::scheduler::TestCase> = req
-        .test_cases
-        .iter()
-        .map(|tc| common::scheduler::TestCase {
-            id: tc.id.clone(),
-            input: tc.input.clone(),
-            expected_output: tc.expected_output.clone(),
-        })
-        .collect();
+    let checker = match (&req.checker, &req.interactor) {
+        (Some(_), Some(_)) => Err("A job takes a checker or an interactor, not both".to_string()),
+        (Some(checker), None) => checker_source(&state, checker, false).map(Some),
+        (None, Some(interactor)) => checker_source(&state, interactor, true).map(Some),
+        (None, None) => Ok(None),
+    };
+    let checker = match checker {
+        Ok(checker) => checker,
+        Err(e) => return submit_error(StatusCode::BAD_REQUEST, e),
+    };
 
-    // Create oneshot channel for response
-    let (tx, _rx) = oneshot::channel::<FinalResponse>();
+    // A job no connected worker has the RAM for would wait forever
+    let memory_mb = scheduler::batch_memory_mb(req.memory_limit_mb, checker.is_some());
+    let checker_language = checker.as_ref().map(|c| c.language.as_str());
+    if !state.workers.is_empty()
+        && !scheduler::is_memory_supported(&state, language, checker_language, memory_mb)
+    {
+        return submit_error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "No connected worker has {} MB of RAM to run this job's tests",
+                memory_mb
+            ),
+        );
+    }
 
-    // Determine initial state based on language type
-    let initial_state = if is_interpreted {
-        JobState::Executing {
-            pending_batches: 1, // Single batch for now
+    if let Some(url) = &req.callback_url {
+        if let Err(e) = webhook::validate_url(url).await {
+            return submit_error(StatusCode::BAD_REQUEST, e);
         }
-    } else {
-        JobState::Compiling
+    }
+
+    let project = match job_project(&req, language) {
+        Ok(project) => project,
+        Err(e) => return submit_error(StatusCode::BAD_REQUEST, e),
+    };
+
+    // Convert comparators and test cases to protobuf format
+    let comparator = match req.comparator.map(ComparatorInput::to_proto).transpose() {
+        Ok(comparator) => comparator,
+        Err(e) => return submit_error(StatusCode::BAD_REQUEST, e),
     };
+    let suite = match job_suite(&state, &req) {
+        Ok(suite) => suite,
+        Err(e) => return submit_error(StatusCode::BAD_REQUEST, e),
+    };
+    let test_cases = match &suite {
+        Some(suite) => suite.test_cases.clone(),
+        None => match test_cases(&req.test_cases)
+            .and_then(|test_cases| suites::unique_ids(&test_cases).map(|()| test_cases))
+        {
+            Ok(test_cases) => Arc::new(test_cases),
+            Err(e) => return submit_error(StatusCode::BAD_REQUEST, e),
+        },
+    };
+    let subtasks = match subtasks(&req.subtasks, &test_cases) {
+        Ok(subtasks) => subtasks,
+        Err(e) => return submit_error(StatusCode::BAD_REQUEST, e),
+    };
+
+    let job_id = Uuid::new_v4().to_string();
+
+    info!(
+        job_id = %job_id,
+        language = %language,
+        test_cases = test_cases.len(),
+        suite = ?req.suite,
+        files = project.as_ref().map_or(0, |p| p.files.len()),
+        "Job submitted"
+    );
+
+    // Create oneshot channel for response
+    let (tx, rx) = oneshot::channel::<FinalResponse>();
 
     // Create job context
-    let job = JobContext {
-        id: job_id.clone(),
-        language: req.language.clone(),
-        source_code: req.source_code.clone(),
-        total_test_cases: req.test_cases.len(),
-        results: vec![],
-        state: initial_state,
-        binary: None,
-        compiler_output: None,
-        responder: Some(tx),
-        test_cases: proto_test_cases.clone(),
-        time_limit_ms: req.time_limit_ms,
-        memory_limit_mb: req.memory_limit_mb,
+    let mut job = JobContext::new(
+        job_id.clone(),
+        language.to_string(),
+        req.source_code.clone(),
+        req.compiler_flags.clone(),
+        test_cases,
+        req.time_limit_ms,
+        req.memory_limit_mb,
+    );
+    job.responder = Some(tx);
+    job.project = project;
+    job.suite = suite;
+    job.isolation = req.isolation.to_proto();
+    job.comparator = comparator;
+    job.checker = checker;
+    job.subtasks = subtasks;
+    job.webhook = req.callback_url.clone().map(|url| Webhook {
+        url,
+        secret: req.callback_secret.clone(),
+    });
+
+    // Stored before it can be scheduled, so its transitions find its row
+    let store = state.store.clone();
+    let stored = tokio::task::spawn_blocking(move || {
+        let stored = store.insert_job(&job);
+        (job, stored)
+    })
+    .await;
+    let job = match stored {
+        Ok((job, Ok(()))) => job,
+        Ok((_, Err(e))) => return store_error(&job_id, e),
+        Err(e) => return store_error(&job_id, e.to_string()),
     };
 
-    // Store job
-    state.jobs.insert(job_id.clone(), job);
+    // Watch for completion before the job can possibly finish
+    if job.webhook.is_some() {
+        tokio::spawn(webhook::watch_job(
+            state.clone(),
+            job_id.clone(),
+            job.events.subscribe(),
+        ));
+    }
 
-    // Find a suitable worker (least loaded)
-    let worker_id = state
-        .workers
-        .iter()
-        .min_by(|a, b| {
-            a.value()
-                .cpu_load_percent
-                .partial_cmp(&b.value().cpu_load_percent)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        })
-        .map(|entry| entry.key().clone());
+    // Store job and queue it; the dispatcher starts it once a worker is free
+    state.jobs.insert(job_id.clone(), job);
+    let queue_position = state.enqueue(&job_id);
 
-    let Some(worker_id) = worker_id else {
+    if !params.wait {
         return (
-            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::ACCEPTED,
             Json(SubmitResponse {
                 job_id,
-                message: "No workers available".to_string(),
+                message: "Job accepted and queued for execution".to_string(),
+                queue_position: Some(queue_position),
             }),
-        );
-    };
+        )
+            .into_response();
+    }
 
-    // Dispatch to worker
-    if is_interpreted {
-        // For interpreted languages, send ExecuteBatchTask directly
-        let task = common::scheduler::ExecuteBatchTask {
-            job_id: job_id.clone(),
-            batch_id: "batch_1".to_string(),
-            language: req.language.clone(),
-            payload: Some(common::scheduler::execute_batch_task::Payload::SourceCode(
-                req.source_code.clone(),
-            )),
-            inputs: proto_test_cases,
-            time_limit_ms: req.time_limit_ms,
-            memory_limit_mb: req.memory_limit_mb,
-        };
+    // Blocking mode: answer with the final result, or fall back to a 202 the
+    // client can poll if the job is still running when the timeout elapses
+    let timeout_ms = params.timeout_ms.min(MAX_WAIT_TIMEOUT_MS);
+    if let Ok(Ok(response)) = tokio::time::timeout(Duration::from_millis(timeout_ms), rx).await {
+        return (StatusCode::OK, Json(StatusResponse::from(response))).into_response();
+    }
 
-        let cmd = common::scheduler::MasterCommand {
-            task: Some(common::scheduler::master_command::Task::Execute(task)),
-        };
+    (
+        StatusCode::ACCEPTED,
+        Json(SubmitResponse {
+            message: format!(
+                "Job still running after {} ms; poll /status/{}",
+                timeout_ms, job_id
+            ),
+            queue_position: state.queue_position(&job_id),
+            job_id,
+        }),
+    )
+        .into_response()
+}
+
+/// Validate a submission's checker or interactor: it must be in a compiled
+/// language that a connected worker can build and run (if any workers are
+/// connected)
+fn checker_source(
+    state: &AppState,
+    checker: &CheckerInput,
+    interactive: bool,
+) -> Result<CheckerSource, String> {
+    let kind = if interactive { "interactor" } else { "checker" };
+    let language = common::canonical_language(&checker.language)
+        .filter(|language| !scheduler::is_interpreted(language))
+        .ok_or_else(|| format!("Unsupported {} language: {}", kind, checker.language))?;
 
-        if let Some(worker) = state.workers.get(&worker_id) {
-            let _ = worker.sender.send(Ok(cmd)).await;
-            info!(job_id = %job_id, worker_id = %worker_id, "Dispatched execute task");
+    if !state.workers.is_empty() && !scheduler::is_language_supported(state, language) {
+        return Err(format!(
+            "No connected worker supports {} language: {}",
+            kind, language
+        ));
+    }
+    if let Some(flag) = disallowed_flag(language, &checker.compiler_flags) {
+        return Err(format!(
+            "Compiler flag not allowed for {} language {}: {}",
+            kind, language, flag
+        ));
+    }
+
+    Ok(CheckerSource {
+        language: language.to_string(),
+        source_code: checker.source_code.clone(),
+        compiler_flags: checker.compiler_flags.clone(),
+        interactive,
+        time_limit_ms: checker.time_limit_ms,
+    })
+}
+
+/// The first of `flags` that is not on the language's allow-list
+fn disallowed_flag<'a>(language: &str, flags: &'a [String]) -> Option<&'a str> {
+    flags
+        .iter()
+        .map(String::as_str)
+        .find(|flag| !common::compiler_flag_allowed(language, flag))
+}
+
+/// The source tree of a project submission, if it is one
+fn job_project(req: &SubmitRequest, language: &str) -> Result<Option<Project>, String> {
+    let files = match (&req.archive, req.files.is_empty()) {
+        (Some(_), false) => return Err("A job takes files or an archive, not both".to_string()),
+        (Some(archive), true) => project::unpack_archive(archive)?,
+        (None, false) => req
+            .files
+            .iter()
+            .map(|f| SourceFile {
+                path: f.path.clone(),
+                content: f.content.clone().into_bytes(),
+            })
+            .collect(),
+        (None, true) if req.build_command.is_some() || req.artifact.is_some() => {
+            return Err("build_command and artifact need files or an archive".to_string());
         }
-    } else {
-        // For compiled languages, send CompileTask first
-        let task = common::scheduler::CompileTask {
-            job_id: job_id.clone(),
-            language: req.language.clone(),
-            source_code: req.source_code.clone(),
-            flags: req.compiler_flags.clone(),
-        };
+        (None, true) => return Ok(None),
+    };
 
-        let cmd = common::scheduler::MasterCommand {
-            task: Some(common::scheduler::master_command::Task::Compile(task)),
-        };
+    if !req.source_code.is_empty() {
+        return Err("A job takes source_code or a project, not both".to_string());
+    }
+    if scheduler::is_interpreted(language) {
+        return Err(format!(
+            "Project submissions need a compiled language, not {}",
+            language
+        ));
+    }
 
-        if let Some(worker) = state.workers.get(&worker_id) {
-            let _ = worker.sender.send(Ok(cmd)).await;
-            info!(job_id = %job_id, worker_id = %worker_id, "Dispatched compile task");
+    let project = Project {
+        files,
+        build_command: req.build_command.clone(),
+        artifact: req.artifact.clone(),
+    };
+    project::validate(&project)?;
+    if let Some(command) = &project.build_command {
+        if common::build_command_argv(language, command).is_none() {
+            return Err(format!(
+                "Build command not allowed for {}: {}",
+                language, command
+            ));
         }
     }
+    Ok(Some(project))
+}
+
+/// Convert test cases to protobuf format
+fn test_cases(inputs: &[TestCaseInput]) -> Result<Vec<TestCase>, String> {
+    inputs
+        .iter()
+        .map(|tc| {
+            let input = match &tc.input_base64 {
+                Some(_) if !tc.input.is_empty() => {
+                    return Err(format!(
+                        "Test case {}: give input or input_base64, not both",
+                        tc.id
+                    ))
+                }
+                Some(encoded) => base64::engine::general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(|e| format!("Test case {}: invalid input_base64: {}", tc.id, e))?,
+                None => tc.input.clone().into_bytes(),
+            };
+            Ok(TestCase {
+                id: tc.id.clone(),
+                input,
+                expected_output: tc.expected_output.clone(),
+                comparator: tc
+                    .comparator
+                    .map(ComparatorInput::to_proto)
+                    .transpose()
+                    .map_err(|e| format!("Test case {}: {}", tc.id, e))?,
+            })
+        })
+        .collect()
+}
+
+/// The suite a submission runs instead of its own test cases, if any
+fn job_suite(state: &AppState, req: &SubmitRequest) -> Result<Option<Arc<Suite>>, String> {
+    let Some(reference) = &req.suite else {
+        return Ok(None);
+    };
+    if !req.test_cases.is_empty() {
+        return Err("A job takes test cases or a suite, not both".to_string());
+    }
+    suites::resolve(state, reference)
+        .map(Some)
+        .ok_or_else(|| format!("Unknown suite: {}", reference))
+}
+
+/// Validate a submission's subtasks: unique IDs, non-negative points and
+/// at least one test each, all of which must exist
+fn subtasks(inputs: &[SubtaskInput], test_cases: &[TestCase]) -> Result<Vec<Subtask>, String> {
+    let test_ids: HashSet<&str> = test_cases.iter().map(|tc| tc.id.as_str()).collect();
+    let mut subtask_ids = HashSet::new();
+
+    inputs
+        .iter()
+        .map(|input| {
+            if !subtask_ids.insert(input.id.as_str()) {
+                return Err(format!("Duplicate subtask: {}", input.id));
+            }
+            if !input.points.is_finite() || input.points < 0.0 {
+                return Err(format!(
+                    "Subtask {}: points must be finite and non-negative",
+                    input.id
+                ));
+            }
+            if input.test_ids.is_empty() {
+                return Err(format!("Subtask {} has no tests", input.id));
+            }
+            if let Some(unknown) = input
+                .test_ids
+                .iter()
+                .find(|id| !test_ids.contains(id.as_str()))
+            {
+                return Err(format!("Subtask {}: unknown test {}", input.id, unknown));
+            }
 
+            Ok(Subtask {
+                id: input.id.clone(),
+                points: input.points,
+                policy: input.policy,
+                test_ids: input.test_ids.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Reply to a submission that could not be stored
+fn store_error(job_id: &str, e: String) -> Response {
+    error!(job_id = %job_id, error = %e, "Failed to store job");
+    submit_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Failed to store job".to_string(),
+    )
+}
+
+/// Reject a submission
+fn submit_error(status: StatusCode, message: String) -> Response {
     (
-        StatusCode::ACCEPTED,
+        status,
         Json(SubmitResponse {
-            job_id,
-            message: "Job accepted and dispatched for execution".to_string(),
+            job_id: String::new(),
+            message,
+            queue_position: None,
         }),
     )
+        .into_response()
 }
 
 async fn get_job_status(
     State(state): State<AppState>,
     Path(job_id): Path<String>,
 ) -> impl IntoResponse {
-    if let Some(job) = state.jobs.get(&job_id) {
-        let state_str = match &job.state {
-            JobState::Compiling => "compiling",
-            JobState::Executing { pending_batches } => {
-                if *pending_batches > 0 {
-                    "executing"
-                } else {
-                    "completed"
+    match state.jobs.get(&job_id) {
+        Some(job) => (StatusCode::OK, Json(StatusResponse::for_job(&state, &job))),
+        None => {
+            let (code, response) = StatusResponse::missing(&state, job_id);
+            (code, Json(response))
+        }
+    }
+}
+
+/// Stream a job's progress as Server-Sent Events: a `snapshot` of the current
+/// status, then `state`, `compile`, `test_result` and finally `finished`
+async fn job_events(State(state): State<AppState>, Path(job_id): Path<String>) -> Response {
+    let (snapshot, finished, mut events) = match state.jobs.get(&job_id) {
+        Some(job) => (
+            StatusResponse::for_job(&state, &job),
+            job.is_finished(),
+            job.events.subscribe(),
+        ),
+        None => {
+            let (code, response) = StatusResponse::missing(&state, job_id);
+            return (code, Json(response)).into_response();
+        }
+    };
+
+    let (tx, rx) = mpsc::channel::<Result<Event, Infallible>>(64);
+    tokio::spawn(async move {
+        if tx.send(Ok(sse_event("snapshot", &snapshot))).await.is_err() || finished {
+            return;
+        }
+
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(job_id = %snapshot.job_id, missed = missed, "Event subscriber lagged");
+                    continue;
                 }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+
+            let done = matches!(event, JobEvent::Finished { .. });
+            if tx.send(Ok(job_event(event))).await.is_err() || done {
+                return;
             }
-            JobState::Completed => "completed",
-        };
+        }
+    });
 
-        (
-            StatusCode::OK,
-            Json(StatusResponse {
-                job_id,
-                state: state_str.to_string(),
-                results: job.results.iter().cloned().map(Into::into).collect(),
-                compiler_output: job.compiler_output.clone(),
-                error: None,
+    Sse::new(ReceiverStream::new(rx))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn sse_event(name: &str, data: &impl Serialize) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .unwrap_or_else(|_| Event::default().event(name))
+}
+
+fn job_event(event: JobEvent) -> Event {
+    match event {
+        JobEvent::State(job_state) => sse_event(
+            "state",
+            &serde_json::json!({ "state": state_str(&job_state) }),
+        ),
+        JobEvent::CompileOutput { success, output } => sse_event(
+            "compile",
+            &serde_json::json!({ "success": success, "compiler_output": output }),
+        ),
+        JobEvent::TestResult(result) => sse_event("test_result", &TestResultOutput::from(result)),
+        JobEvent::Finished {
+            state: job_state,
+            success,
+            error,
+        } => sse_event(
+            "finished",
+            &serde_json::json!({
+                "state": state_str(&job_state),
+                "success": success,
+                "error": error,
             }),
-        )
-    } else {
-        (
+        ),
+    }
+}
+
+async fn cancel_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    {
+        let Some(mut job) = state.jobs.get_mut(&job_id) else {
+            if state.is_expired(&job_id) {
+                return (
+                    StatusCode::GONE,
+                    Json(CancelResponse {
+                        job_id,
+                        state: "expired".to_string(),
+                        message: "Job results expired".to_string(),
+                    }),
+                );
+            }
+            return (
+                StatusCode::NOT_FOUND,
+                Json(CancelResponse {
+                    job_id,
+                    state: "not_found".to_string(),
+                    message: "Job not found".to_string(),
+                }),
+            );
+        };
+
+        if job.is_finished() {
+            let state_str = state_str(&job.state);
+            return (
+                StatusCode::CONFLICT,
+                Json(CancelResponse {
+                    job_id,
+                    state: state_str.to_string(),
+                    message: format!("Job already {}", state_str),
+                }),
+            );
+        }
+
+        job.cancel();
+        state.persist(&job);
+    }
+
+    let workers = scheduler::cancel_job_tasks(&state, &job_id).await;
+    info!(job_id = %job_id, workers = workers, "Job cancelled");
+
+    (
+        StatusCode::OK,
+        Json(CancelResponse {
+            job_id,
+            state: "cancelled".to_string(),
+            message: "Job cancelled".to_string(),
+        }),
+    )
+}
+
+/// Upload a test suite; re-uploading the latest version's test cases
+/// returns that version instead of creating a new one
+async fn create_suite(State(state): State<AppState>, Json(req): Json<SuiteRequest>) -> Response {
+    let test_cases = match test_cases(&req.test_cases) {
+        Ok(test_cases) => test_cases,
+        Err(e) => return suite_error(StatusCode::BAD_REQUEST, e),
+    };
+    if let Err(e) = suites::validate(&req.name, &test_cases) {
+        return suite_error(StatusCode::BAD_REQUEST, e);
+    }
+
+    match suites::register(&state, &req.name, test_cases).await {
+        Ok((suite, created)) => {
+            info!(
+                suite = %suite.reference(),
+                hash = %suite.hash,
+                test_cases = suite.test_cases.len(),
+                created = created,
+                "Suite uploaded"
+            );
+            let status = if created {
+                StatusCode::CREATED
+            } else {
+                StatusCode::OK
+            };
+            (status, Json(SuiteResponse::from(suite.as_ref()))).into_response()
+        }
+        Err(e) => {
+            error!(suite = %req.name, error = %e, "Failed to store suite");
+            suite_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to store suite".to_string(),
+            )
+        }
+    }
+}
+
+/// Look up a suite by `name@version`, or its latest version by `name`
+async fn get_suite(State(state): State<AppState>, Path(reference): Path<String>) -> Response {
+    match suites::resolve(&state, &reference) {
+        Some(suite) => Json(SuiteResponse::from(suite.as_ref())).into_response(),
+        None => suite_error(
             StatusCode::NOT_FOUND,
-            Json(StatusResponse {
-                job_id,
-                state: "not_found".to_string(),
-                results: vec![],
-                compiler_output: None,
-                error: Some("Job not found".to_string()),
-            }),
-        )
+            format!("Unknown suite: {}", reference),
+        ),
     }
 }
 
+fn suite_error(status: StatusCode, message: String) -> Response {
+    (status, Json(serde_json::json!({ "message": message }))).into_response()
+}
+
 async fn list_workers(State(state): State<AppState>) -> impl IntoResponse {
     let workers: Vec<_> = state
         .workers
@@ -297,10 +1100,86 @@ async fn list_workers(State(state): State<AppState>) -> impl IntoResponse {
                 "cpu_load_percent": entry.value().cpu_load_percent,
                 "ram_usage_mb": entry.value().ram_usage_mb,
                 "active_tasks": entry.value().active_tasks,
+                "execution_slots": entry.value().execution_slots,
+                "free_slots": entry.value().free_slots(),
+                "reserved_ram_mb": entry.value().reserved_ram_mb(),
                 "tags": entry.value().tags,
+                "compile_languages": entry.value().compile_languages,
+                "run_languages": entry.value().run_languages,
+                "liveness": entry.value().liveness.as_str(),
+                "last_heartbeat_ms_ago": entry.value().last_heartbeat.elapsed().as_millis() as u64,
+                "pools": entry.value().pools.iter().map(|pool| {
+                    serde_json::json!({
+                        "kind": pool.kind,
+                        "image": pool.image,
+                        "idle": pool.idle,
+                        "leased": pool.leased,
+                        "hits": pool.hits,
+                        "misses": pool.misses,
+                        "recycled": pool.recycled,
+                    })
+                }).collect::<Vec<_>>(),
             })
         })
         .collect();
 
     Json(serde_json::json!({ "workers": workers }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::WorkerInfo;
+    use crate::store::SqliteJobStore;
+
+    async fn submit(state: &AppState, req: serde_json::Value) -> StatusCode {
+        let params = SubmitParams {
+            wait: false,
+            timeout_ms: default_wait_timeout(),
+        };
+        let req = serde_json::from_value(req).unwrap();
+        submit_job(State(state.clone()), Query(params), Json(req))
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn rejects_jobs_no_worker_has_the_ram_for() {
+        let state = AppState::new(Arc::new(SqliteJobStore::open(":memory:").unwrap()));
+        state
+            .workers
+            .insert("w1".to_string(), WorkerInfo::idle(1024, &["cpp"]));
+        let job = |memory_limit_mb: u32| {
+            serde_json::json!({
+                "language": "cpp",
+                "source_code": "int main() {}",
+                "memory_limit_mb": memory_limit_mb,
+            })
+        };
+
+        assert_eq!(submit(&state, job(2048)).await, StatusCode::BAD_REQUEST);
+        assert_eq!(submit(&state, job(0)).await, StatusCode::BAD_REQUEST);
+        assert_eq!(submit(&state, job(512)).await, StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn rejects_duplicate_test_ids() {
+        let state = AppState::new(Arc::new(SqliteJobStore::open(":memory:").unwrap()));
+        let job = |ids: [&str; 2]| {
+            serde_json::json!({
+                "language": "cpp",
+                "source_code": "int main() {}",
+                "test_cases": ids.map(|id| serde_json::json!({
+                    "id": id,
+                    "expected_output": "",
+                })),
+            })
+        };
+
+        assert_eq!(
+            submit(&state, job(["1", "1"])).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(submit(&state, job(["1", "2"])).await, StatusCode::ACCEPTED);
+    }
+}
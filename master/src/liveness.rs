@@ -0,0 +1,114 @@
+//! Master Node - Worker Liveness
+//!
+//! Tracks heartbeat age per worker, marks workers that stop reporting as
+//! suspect and evicts them (re-dispatching their work) if they stay silent.
+
+use crate::scheduler;
+use crate::state::{AppState, WorkerLiveness};
+use common::scheduler::{master_command::Task, MasterCommand, ShutdownRequest};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Interval at which workers send heartbeats (must match the worker)
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+const DEFAULT_SUSPECT_AFTER_MISSED: u32 = 2;
+const DEFAULT_EVICT_AFTER_MISSED: u32 = 6;
+
+/// Liveness thresholds, in missed heartbeat intervals
+#[derive(Debug, Clone, Copy)]
+pub struct LivenessConfig {
+    /// Missed heartbeats after which a worker is marked suspect
+    pub suspect_after_missed: u32,
+    /// Missed heartbeats after which a worker is evicted
+    pub evict_after_missed: u32,
+}
+
+impl LivenessConfig {
+    /// Read thresholds from `SUSPECT_AFTER_MISSED_HEARTBEATS` and
+    /// `EVICT_AFTER_MISSED_HEARTBEATS`, falling back to the defaults
+    pub fn from_env() -> Self {
+        let read = |name: &str, default: u32| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+
+        let suspect_after_missed = read(
+            "SUSPECT_AFTER_MISSED_HEARTBEATS",
+            DEFAULT_SUSPECT_AFTER_MISSED,
+        );
+        let evict_after_missed = read("EVICT_AFTER_MISSED_HEARTBEATS", DEFAULT_EVICT_AFTER_MISSED)
+            .max(suspect_after_missed + 1);
+
+        Self {
+            suspect_after_missed,
+            evict_after_missed,
+        }
+    }
+
+    fn suspect_after(&self) -> Duration {
+        HEARTBEAT_INTERVAL * self.suspect_after_missed
+    }
+
+    fn evict_after(&self) -> Duration {
+        HEARTBEAT_INTERVAL * self.evict_after_missed
+    }
+}
+
+/// Periodically re-evaluate every worker's liveness
+pub async fn run_liveness_monitor(state: AppState, config: LivenessConfig) {
+    info!(
+        suspect_after_missed = config.suspect_after_missed,
+        evict_after_missed = config.evict_after_missed,
+        "Worker liveness monitor started"
+    );
+
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        check_workers(&state, &config);
+    }
+}
+
+fn check_workers(state: &AppState, config: &LivenessConfig) {
+    let now = Instant::now();
+    let mut evicted = Vec::new();
+
+    for mut entry in state.workers.iter_mut() {
+        let silent_for = now.duration_since(entry.last_heartbeat);
+
+        if silent_for >= config.evict_after() {
+            evicted.push(entry.key().clone());
+        } else if silent_for >= config.suspect_after() && entry.liveness == WorkerLiveness::Alive {
+            warn!(
+                worker_id = %entry.key(),
+                silent_secs = silent_for.as_secs(),
+                "Worker missed heartbeats, marking suspect"
+            );
+            entry.liveness = WorkerLiveness::Suspect;
+        }
+    }
+
+    for worker_id in evicted {
+        let Some((_, worker)) = state.workers.remove(&worker_id) else {
+            continue;
+        };
+
+        warn!(
+            worker_id = %worker_id,
+            in_flight = worker.assigned.len(),
+            "Evicting worker after missed heartbeats"
+        );
+
+        // If the worker is actually alive it will reconnect and re-register
+        let _ = worker.sender.try_send(Ok(MasterCommand {
+            task: Some(Task::Shutdown(ShutdownRequest {
+                reason: "Evicted after missed heartbeats".to_string(),
+            })),
+        }));
+
+        scheduler::requeue_tasks(state, &worker_id, worker.assigned);
+    }
+}
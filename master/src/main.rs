@@ -5,20 +5,32 @@
 //! - Exposes an HTTP API for client submissions (port 8080)
 //! - Orchestrates the split-phase execution pipeline
 
+mod grading;
 mod grpc;
 mod http;
+mod liveness;
+mod project;
+mod retention;
 mod scheduler;
 mod state;
+mod store;
+mod suites;
+mod webhook;
 
 use common::scheduler::worker_service_server::WorkerServiceServer;
 use grpc::WorkerServiceImpl;
+use liveness::LivenessConfig;
+use retention::RetentionConfig;
 use state::AppState;
+use std::sync::Arc;
+use store::SqliteJobStore;
 use tonic::transport::Server as TonicServer;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
 const GRPC_ADDR: &str = "0.0.0.0:50051";
 const HTTP_ADDR: &str = "0.0.0.0:8080";
+const DEFAULT_JOB_STORE_PATH: &str = "master.db";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -31,8 +43,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Starting Master Node...");
 
-    // Create shared state
-    let state = AppState::new();
+    // Open the job store and create shared state
+    let store_path =
+        std::env::var("JOB_STORE_PATH").unwrap_or_else(|_| DEFAULT_JOB_STORE_PATH.to_string());
+    let store = SqliteJobStore::open(&store_path)?;
+    info!(path = %store_path, "Opened job store");
+
+    let state = AppState::new(Arc::new(store));
+
+    // Restore jobs from before a restart; unfinished ones are queued again
+    scheduler::resume_jobs(&state)?;
+
+    // Start dispatcher for queued jobs
+    tokio::spawn(scheduler::run_dispatcher(state.clone()));
+
+    // Start heartbeat monitor for suspect/stale workers
+    tokio::spawn(liveness::run_liveness_monitor(
+        state.clone(),
+        LivenessConfig::from_env(),
+    ));
+
+    // Start sweeper that evicts old finished jobs
+    tokio::spawn(retention::run_retention_sweeper(
+        state.clone(),
+        RetentionConfig::from_env(),
+    ));
 
     // Start gRPC server for workers
     let grpc_state = state.clone();
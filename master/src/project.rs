@@ -0,0 +1,168 @@
+//! Master Node - Project Submissions
+//!
+//! A submission can be a source tree rather than a single file: files given
+//! inline or as a base64-encoded tar archive. Workers upload the tree into the
+//! build container and run the submitter's build command (one of a few allowed
+//! per language), or a per-language default, then collect the declared
+//! artifact.
+
+use base64::Engine;
+use common::scheduler::SourceFile;
+use common::MAX_PROJECT_PATH_LEN;
+use std::collections::HashSet;
+use std::io::Read;
+
+/// Most files a project may contain
+const MAX_FILES: usize = 1000;
+/// Largest total size of a project's files
+const MAX_PROJECT_BYTES: usize = 16 * 1024 * 1024;
+/// Largest submission request: an archive is base64-encoded, a third larger
+/// than the files it carries, and tar adds up to 1 KB per file
+pub const MAX_REQUEST_BYTES: usize = MAX_PROJECT_BYTES / 3 * 4 + 4 * 1024 * 1024;
+/// Absolute artifact paths a project may name: where the default builds
+/// leave a binary and Java classes
+const DEFAULT_ARTIFACTS: [&str; 2] = ["/tmp/main", "/tmp/classes"];
+
+/// Source tree and build settings of a multi-file submission
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub files: Vec<SourceFile>,
+    /// Allowed build command run in the project root; a per-language default
+    /// if absent
+    pub build_command: Option<String>,
+    /// Path of what the build produces; a per-language default if absent
+    pub artifact: Option<String>,
+}
+
+impl Project {
+    /// Total size of the project's files in bytes
+    pub fn size(&self) -> usize {
+        self.files
+            .iter()
+            .map(|f| f.path.len() + f.content.len())
+            .sum()
+    }
+}
+
+/// Unpack a base64-encoded (uncompressed) tar archive into source files;
+/// directories are skipped and links rejected
+pub fn unpack_archive(encoded: &str) -> Result<Vec<SourceFile>, String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("Archive is not valid base64: {}", e))?;
+
+    let mut archive = tar::Archive::new(data.as_slice());
+    let entries = archive
+        .entries()
+        .map_err(|e| format!("Invalid tar archive: {}", e))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let mut entry = entry.map_err(|e| format!("Invalid tar archive: {}", e))?;
+        let kind = entry.header().entry_type();
+        if kind.is_dir() {
+            continue;
+        }
+
+        let path = entry
+            .path()
+            .map_err(|e| format!("Invalid path in archive: {}", e))?
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .to_string();
+        if !kind.is_file() {
+            return Err(format!("Archive entry {} is not a regular file", path));
+        }
+        if files.len() >= MAX_FILES || entry.size() as usize > MAX_PROJECT_BYTES {
+            return Err("Archive is too large".to_string());
+        }
+
+        let mut content = Vec::new();
+        entry
+            .read_to_end(&mut content)
+            .map_err(|e| format!("Failed to read {} from archive: {}", path, e))?;
+        files.push(SourceFile { path, content });
+    }
+    Ok(files)
+}
+
+/// Check a project: a bounded number of files and bytes, with unique
+/// relative paths that stay inside the project root
+pub fn validate(project: &Project) -> Result<(), String> {
+    if project.files.is_empty() {
+        return Err("Project has no files".to_string());
+    }
+    if project.files.len() > MAX_FILES {
+        return Err(format!("Project has more than {} files", MAX_FILES));
+    }
+    if project.size() > MAX_PROJECT_BYTES {
+        return Err(format!(
+            "Project is larger than {} bytes",
+            MAX_PROJECT_BYTES
+        ));
+    }
+
+    let mut paths = HashSet::new();
+    for file in &project.files {
+        if !is_relative_path(&file.path) {
+            return Err(format!("Invalid file path: {}", file.path));
+        }
+        if !paths.insert(file.path.as_str()) {
+            return Err(format!("Duplicate file path: {}", file.path));
+        }
+    }
+
+    if let Some(command) = &project.build_command {
+        if command.trim().is_empty() {
+            return Err("Build command is empty".to_string());
+        }
+    }
+    if let Some(artifact) = &project.artifact {
+        // The artifact is passed to `cp` on the worker, so keep it plain and
+        // inside the project root, or where the default builds leave it
+        let plain = artifact
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+        let inside = is_relative_path(artifact) || DEFAULT_ARTIFACTS.contains(&artifact.as_str());
+        if !plain || !inside {
+            return Err(format!("Invalid artifact path: {}", artifact));
+        }
+    }
+    Ok(())
+}
+
+/// A non-empty relative path without `.`, `..` or empty components
+fn is_relative_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.len() <= MAX_PROJECT_PATH_LEN
+        && !path.contains(['\0', '\\'])
+        && path
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(artifact: &str) -> Project {
+        Project {
+            files: vec![SourceFile {
+                path: "main.c".to_string(),
+                content: b"int main() {}".to_vec(),
+            }],
+            build_command: Some("make".to_string()),
+            artifact: Some(artifact.to_string()),
+        }
+    }
+
+    #[test]
+    fn artifacts_stay_inside_the_project() {
+        assert!(validate(&project("build/app")).is_ok());
+        assert!(validate(&project("/tmp/main")).is_ok());
+        assert!(validate(&project("/tmp/classes")).is_ok());
+        assert!(validate(&project("/etc/passwd")).is_err());
+        assert!(validate(&project("/usr")).is_err());
+        assert!(validate(&project("../main")).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//! Master Node - Job Retention
+//!
+//! Bounds how many finished jobs the master keeps in memory: jobs are evicted
+//! once they exceed a maximum age, and the oldest ones go first whenever the
+//! retained count or size is over its limit. A job whose completion webhook
+//! is still being delivered is kept until delivery succeeds or gives up.
+
+use crate::state::AppState;
+use crate::store::StoreWrite;
+use crate::webhook;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// How often the sweeper runs
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+const DEFAULT_MAX_AGE_SECS: u64 = 3600;
+const DEFAULT_MAX_JOBS: usize = 1000;
+const DEFAULT_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+/// Limits on finished jobs kept in memory
+#[derive(Debug, Clone, Copy)]
+pub struct RetentionConfig {
+    /// How long a finished job is kept
+    pub max_age: Duration,
+    /// How many finished jobs are kept at most
+    pub max_jobs: usize,
+    /// Total approximate size of finished jobs kept at most
+    pub max_bytes: usize,
+}
+
+impl RetentionConfig {
+    /// Read limits from `JOB_RETENTION_SECS`, `JOB_RETENTION_MAX_JOBS` and
+    /// `JOB_RETENTION_MAX_BYTES`, falling back to the defaults
+    pub fn from_env() -> Self {
+        fn read<T: std::str::FromStr>(name: &str, default: T) -> T {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            max_age: Duration::from_secs(read("JOB_RETENTION_SECS", DEFAULT_MAX_AGE_SECS)),
+            max_jobs: read("JOB_RETENTION_MAX_JOBS", DEFAULT_MAX_JOBS),
+            max_bytes: read("JOB_RETENTION_MAX_BYTES", DEFAULT_MAX_BYTES),
+        }
+    }
+}
+
+/// Periodically evict finished jobs beyond the retention limits
+pub async fn run_retention_sweeper(state: AppState, config: RetentionConfig) {
+    info!(
+        max_age_secs = config.max_age.as_secs(),
+        max_jobs = config.max_jobs,
+        max_bytes = config.max_bytes,
+        "Job retention sweeper started"
+    );
+
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        sweep_jobs(&state, &config);
+    }
+}
+
+fn sweep_jobs(state: &AppState, config: &RetentionConfig) {
+    let now = Instant::now();
+
+    // Finished jobs, oldest first, and whether their webhook is still pending
+    let mut finished: Vec<(String, Instant, usize, bool)> = state
+        .jobs
+        .iter()
+        .filter_map(|entry| {
+            let finished_at = entry.finished_at?;
+            Some((
+                entry.key().clone(),
+                finished_at,
+                entry.approx_bytes(),
+                webhook::pending(&entry),
+            ))
+        })
+        .collect();
+    finished.sort_by_key(|(_, finished_at, _, _)| *finished_at);
+
+    let mut count = finished.len();
+    let mut bytes: usize = finished.iter().map(|(_, _, size, _)| size).sum();
+    let mut evicted = Vec::new();
+
+    for (job_id, finished_at, size, delivering) in finished {
+        let too_old = now.duration_since(finished_at) >= config.max_age;
+        if !too_old && count <= config.max_jobs && bytes <= config.max_bytes {
+            break;
+        }
+        // Delivery loads the job and records its attempts on it
+        if delivering {
+            continue;
+        }
+
+        count -= 1;
+        bytes -= size;
+        evicted.push(job_id);
+    }
+
+    if evicted.is_empty() {
+        return;
+    }
+
+    evicted.retain(|job_id| {
+        state
+            .jobs
+            .remove_if(job_id, |_, job| job.is_finished() && !webhook::pending(job))
+            .is_some()
+    });
+
+    for job_id in &evicted {
+        state.writer.send(StoreWrite::Expire {
+            job_id: job_id.clone(),
+        });
+    }
+
+    let mut expired = state.expired.lock().unwrap();
+    for job_id in &evicted {
+        expired.insert(job_id.clone());
+    }
+    drop(expired);
+
+    info!(
+        evicted = evicted.len(),
+        retained = count,
+        retained_bytes = bytes,
+        "Evicted finished jobs"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::JobContext;
+    use crate::store::SqliteJobStore;
+    use crate::webhook::Webhook;
+    use std::sync::Arc;
+
+    fn finished_job(id: &str, webhook: Option<&str>) -> JobContext {
+        let mut job = JobContext::new(
+            id.to_string(),
+            "python".to_string(),
+            "print(1)".to_string(),
+            vec![],
+            Arc::new(vec![]),
+            1000,
+            128,
+        );
+        job.webhook = webhook.map(|url| Webhook {
+            url: url.to_string(),
+            secret: None,
+        });
+        job.complete();
+        job
+    }
+
+    #[test]
+    fn jobs_with_pending_webhooks_are_not_evicted() {
+        let state = AppState::new(Arc::new(SqliteJobStore::open(":memory:").unwrap()));
+        for job in [
+            finished_job("plain", None),
+            finished_job("delivering", Some("https://example.com/hook")),
+        ] {
+            state.jobs.insert(job.id.clone(), job);
+        }
+        let config = RetentionConfig {
+            max_age: Duration::ZERO,
+            max_jobs: 0,
+            max_bytes: 0,
+        };
+
+        sweep_jobs(&state, &config);
+
+        assert!(state.is_expired("plain"));
+        assert!(state.jobs.contains_key("delivering"));
+    }
+}
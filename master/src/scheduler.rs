@@ -1,144 +1,834 @@
 //! Master Node - Job Scheduler
 //!
-//! Handles worker selection and test case batching.
+//! Handles slot-based worker selection, test case batching, the dispatch
+//! queue and re-dispatch of work lost with a disconnected worker.
 
-use crate::state::AppState;
+use crate::grading::Verdict;
+use crate::state::{AppState, AssignedTask, JobContext, JobState, WorkerInfo};
+use crate::suites::{self, Suite};
+use crate::webhook;
 use common::scheduler::{
-    execute_batch_task, CompileTask, ExecuteBatchTask, MasterCommand, TestCase,
+    execute_batch_task, master_command, CancelTask, Checker, CompileTask, ExecuteBatchTask,
+    MasterCommand, TestCase,
 };
-use tracing::info;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tonic::Status;
+use tracing::{info, warn};
 
 /// Batch size for distributing test cases
 const BATCH_SIZE: usize = 20;
 
-/// Select a worker capable of compilation (has "can_compile" tag and low load)
-pub fn select_compile_worker(state: &AppState) -> Option<String> {
+/// Memory reserved for a compile task (matches the worker's build container limit)
+const COMPILE_MEMORY_MB: u64 = 512;
+
+/// Memory reserved for a batch's checker (matches the worker's checker container limit)
+const CHECKER_MEMORY_MB: u64 = 256;
+
+/// Maximum number of times a compile task or batch is dispatched before its job fails
+const MAX_TASK_ATTEMPTS: u32 = 3;
+
+/// How often the dispatcher re-checks the queue even without a wake-up
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interpreted languages skip Phase 1 and run their source directly
+pub fn is_interpreted(language: &str) -> bool {
+    matches!(
+        language.to_lowercase().as_str(),
+        "python" | "python3" | "javascript" | "js" | "node" | "ruby"
+    )
+}
+
+/// Select the worker with the most free slots that can fit a task needing
+/// `memory_mb` and passes `filter`; ties go to the lower CPU load
+fn select_worker(
+    state: &AppState,
+    memory_mb: u64,
+    filter: impl Fn(&WorkerInfo) -> bool,
+) -> Option<String> {
     state
         .workers
         .iter()
-        .filter(|entry| {
-            entry.value().tags.contains(&"can_compile".to_string())
-                && entry.value().cpu_load_percent < 50.0
-        })
-        .min_by(|a, b| {
-            a.value()
-                .cpu_load_percent
-                .partial_cmp(&b.value().cpu_load_percent)
-                .unwrap_or(std::cmp::Ordering::Equal)
+        .filter(|entry| entry.value().can_fit(memory_mb) && filter(entry.value()))
+        .max_by(|a, b| {
+            a.value().free_slots().cmp(&b.value().free_slots()).then(
+                b.value()
+                    .cpu_load_percent
+                    .partial_cmp(&a.value().cpu_load_percent)
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            )
         })
         .map(|entry| entry.key().clone())
 }
 
-/// Select workers for execution (round robin with load consideration)
-pub fn select_execution_workers(state: &AppState, count: usize) -> Vec<String> {
-    let mut workers: Vec<_> = state
+/// Select a worker that can compile `language` with a free slot
+pub fn select_compile_worker(state: &AppState, language: &str) -> Option<String> {
+    select_worker(state, COMPILE_MEMORY_MB, |worker| {
+        worker.compile_languages.contains(language)
+    })
+}
+
+/// Select a worker that can run `language` (and the checker's language, if
+/// any) with a free slot and `memory_mb` of free RAM
+pub fn select_execution_worker(
+    state: &AppState,
+    language: &str,
+    checker_language: Option<&str>,
+    memory_mb: u64,
+) -> Option<String> {
+    select_worker(state, memory_mb, |worker| {
+        worker.run_languages.contains(language)
+            && checker_language.is_none_or(|l| worker.run_languages.contains(l))
+    })
+}
+
+/// Memory reserved for one batch: the submission's limit plus its checker's
+pub fn batch_memory_mb(memory_limit_mb: u32, has_checker: bool) -> u64 {
+    memory_limit_mb as u64 + if has_checker { CHECKER_MEMORY_MB } else { 0 }
+}
+
+/// Whether a connected worker that runs `language` (and the checker's
+/// language, if any) has `memory_mb` of RAM in total, so that a batch needing
+/// it fits once that worker is idle
+pub fn is_memory_supported(
+    state: &AppState,
+    language: &str,
+    checker_language: Option<&str>,
+    memory_mb: u64,
+) -> bool {
+    state.workers.iter().any(|entry| {
+        let worker = entry.value();
+        worker.total_ram_mb >= memory_mb
+            && worker.run_languages.contains(language)
+            && checker_language.is_none_or(|l| worker.run_languages.contains(l))
+    })
+}
+
+/// Whether any connected worker can take `language` through every phase it needs
+pub fn is_language_supported(state: &AppState, language: &str) -> bool {
+    let can_compile = is_interpreted(language)
+        || state
+            .workers
+            .iter()
+            .any(|entry| entry.value().compile_languages.contains(language));
+    let can_run = state
         .workers
         .iter()
-        .filter(|entry| entry.value().cpu_load_percent < 80.0)
-        .map(|entry| (entry.key().clone(), entry.value().cpu_load_percent))
-        .collect();
+        .any(|entry| entry.value().run_languages.contains(language));
+
+    can_compile && can_run
+}
+
+/// Build the batch ID for the batch at `index` (in original test order)
+pub fn batch_id(index: usize) -> String {
+    format!("batch_{}", index + 1)
+}
+
+/// Build the execute task for one batch of a job
+fn batch_task(job: &JobContext, batch_id: &str) -> Option<ExecuteBatchTask> {
+    let index = job.batch_ids.iter().position(|id| id == batch_id)?;
+    let chunk = job.test_cases.chunks(BATCH_SIZE).nth(index)?;
+
+    // Workers fill in the inputs and expected outputs from their copy of the suite
+    let (inputs, suite_hash) = match &job.suite {
+        Some(suite) => (
+            chunk
+                .iter()
+                .map(|tc| TestCase {
+                    id: tc.id.clone(),
+                    comparator: tc.comparator,
+                    ..Default::default()
+                })
+                .collect(),
+            suite.hash.clone(),
+        ),
+        None => (chunk.to_vec(), String::new()),
+    };
 
-    // Sort by CPU load (ascending)
-    workers.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+    let payload = match &job.binary {
+        Some(bin) => execute_batch_task::Payload::BinaryArtifact(bin.clone()),
+        None => execute_batch_task::Payload::SourceCode(job.source_code.clone()),
+    };
 
-    workers.into_iter().take(count).map(|(id, _)| id).collect()
+    Some(ExecuteBatchTask {
+        job_id: job.id.clone(),
+        batch_id: batch_id.to_string(),
+        language: job.language.clone(),
+        payload: Some(payload),
+        inputs,
+        time_limit_ms: job.time_limit_ms,
+        memory_limit_mb: job.memory_limit_mb,
+        isolation: job.isolation as i32,
+        comparator: job.comparator,
+        checker: job
+            .checker
+            .as_ref()
+            .zip(job.checker_binary.as_ref())
+            .map(|(checker, binary)| Checker {
+                language: checker.language.clone(),
+                binary: binary.clone(),
+                interactive: checker.interactive,
+                time_limit_ms: checker.time_limit_ms,
+            }),
+        suite_hash,
+    })
 }
 
-/// Split test cases into batches
-pub fn create_batches(test_cases: Vec<TestCase>) -> Vec<Vec<TestCase>> {
-    test_cases.chunks(BATCH_SIZE).map(|c| c.to_vec()).collect()
+/// Phase 2: split a job's test cases into batches awaiting dispatch
+///
+/// The job moves to `JobState::Executing` with every batch pending; the
+/// dispatcher then places batches as execution slots become free. A job
+/// without test cases completes immediately.
+pub fn prepare_execution(job: &mut JobContext) {
+    let batches = job.test_cases.len().div_ceil(BATCH_SIZE);
+
+    job.batch_ids = (0..batches).map(batch_id).collect();
+    job.batch_results.clear();
+    job.results.clear();
+    job.undispatched_batches = job.batch_ids.iter().cloned().collect();
+
+    if batches == 0 {
+        job.complete();
+    } else {
+        job.set_state(JobState::Executing {
+            pending_batches: batches,
+        });
+    }
 }
 
-/// Send a compile task to a specific worker
+/// Reserve a slot (and `memory_mb`) for `task` on a worker, returning its sender
+fn reserve_slot(
+    state: &AppState,
+    worker_id: &str,
+    task: &AssignedTask,
+    memory_mb: u64,
+) -> Result<mpsc::Sender<Result<MasterCommand, Status>>, String> {
+    let mut worker = state
+        .workers
+        .get_mut(worker_id)
+        .ok_or_else(|| format!("Worker {} not found", worker_id))?;
+
+    if !worker.can_fit(memory_mb) {
+        return Err(format!("Worker {} has no free slot", worker_id));
+    }
+
+    worker.assigned.insert(task.clone(), memory_mb);
+    Ok(worker.sender.clone())
+}
+
+/// Release the slot held by an in-flight task once its worker has reported back
+pub fn release_slot(state: &AppState, worker_id: &str, task: &AssignedTask) {
+    if let Some(mut worker) = state.workers.get_mut(worker_id) {
+        worker.assigned.remove(task);
+    }
+}
+
+/// Reserve a slot for `task`, send `cmd` and count the attempt
+async fn send_task(
+    state: &AppState,
+    worker_id: &str,
+    task: AssignedTask,
+    memory_mb: u64,
+    cmd: MasterCommand,
+) -> Result<(), String> {
+    let sender = reserve_slot(state, worker_id, &task, memory_mb)?;
+
+    if let Err(e) = sender.send(Ok(cmd)).await {
+        release_slot(state, worker_id, &task);
+        return Err(format!("Failed to send task: {}", e));
+    }
+
+    if let Some(mut job) = state.jobs.get_mut(task.job_id()) {
+        *job.dispatch_attempts.entry(task).or_insert(0) += 1;
+    }
+
+    Ok(())
+}
+
+/// Send a job's compile task (for its checker if `checker`) to a specific worker
 pub async fn dispatch_compile_task(
     state: &AppState,
     worker_id: &str,
     job_id: &str,
-    language: &str,
-    source_code: &str,
-    flags: Vec<String>,
+    checker: bool,
 ) -> Result<(), String> {
-    if let Some(worker) = state.workers.get(worker_id) {
-        let cmd = MasterCommand {
-            task: Some(common::scheduler::master_command::Task::Compile(
+    let task = {
+        let job = state
+            .jobs
+            .get(job_id)
+            .ok_or_else(|| format!("Job {} not found", job_id))?;
+
+        match (&job.checker, checker) {
+            (Some(source), true) => CompileTask {
+                job_id: job_id.to_string(),
+                language: source.language.clone(),
+                source_code: source.source_code.clone(),
+                flags: source.compiler_flags.clone(),
+                checker: true,
+                ..Default::default()
+            },
+            (None, true) => return Err(format!("Job {} has no checker", job_id)),
+            (_, false) => {
+                let project = job.project.as_ref();
                 CompileTask {
                     job_id: job_id.to_string(),
-                    language: language.to_string(),
-                    source_code: source_code.to_string(),
-                    flags,
-                },
-            )),
+                    language: job.language.clone(),
+                    source_code: job.source_code.clone(),
+                    flags: job.compiler_flags.clone(),
+                    checker: false,
+                    files: project.map(|p| p.files.clone()).unwrap_or_default(),
+                    build_command: project
+                        .and_then(|p| p.build_command.clone())
+                        .unwrap_or_default(),
+                    artifact: project.and_then(|p| p.artifact.clone()).unwrap_or_default(),
+                }
+            }
+        }
+    };
+
+    let cmd = MasterCommand {
+        task: Some(common::scheduler::master_command::Task::Compile(task)),
+    };
+
+    let job_id = job_id.to_string();
+    let assigned = if checker {
+        AssignedTask::CheckerCompile { job_id }
+    } else {
+        AssignedTask::Compile { job_id }
+    };
+    send_task(state, worker_id, assigned.clone(), COMPILE_MEMORY_MB, cmd).await?;
+
+    info!(
+        job_id = %assigned.job_id(),
+        worker_id = %worker_id,
+        checker = checker,
+        "Dispatched compile task"
+    );
+
+    Ok(())
+}
+
+/// Send an execute batch task to a specific worker
+pub async fn dispatch_execute_task(
+    state: &AppState,
+    worker_id: &str,
+    task: ExecuteBatchTask,
+) -> Result<(), String> {
+    let job_id = task.job_id.clone();
+    let batch_id = task.batch_id.clone();
+    let memory_mb = batch_memory_mb(task.memory_limit_mb, task.checker.is_some());
+
+    let cmd = MasterCommand {
+        task: Some(common::scheduler::master_command::Task::Execute(task)),
+    };
+
+    send_task(
+        state,
+        worker_id,
+        AssignedTask::Batch {
+            job_id: job_id.clone(),
+            batch_id: batch_id.clone(),
+        },
+        memory_mb,
+        cmd,
+    )
+    .await?;
+
+    info!(
+        job_id = %job_id,
+        batch_id = %batch_id,
+        worker_id = %worker_id,
+        "Dispatched execute task"
+    );
+
+    Ok(())
+}
+
+/// Put tasks that were in flight on a worker that went away back in the queue
+///
+/// Tasks whose job has moved on (already compiled, batch already reported,
+/// job finished) are dropped. A task that has already been dispatched
+/// `MAX_TASK_ATTEMPTS` times fails its job; otherwise its job moves to the
+/// front of the queue so the task is placed on the next free slot.
+pub fn requeue_tasks(state: &AppState, lost_worker_id: &str, tasks: HashMap<AssignedTask, u64>) {
+    for task in tasks.into_keys() {
+        let Some(mut job) = state.jobs.get_mut(task.job_id()) else {
+            continue;
         };
 
-        worker
-            .sender
-            .send(Ok(cmd))
-            .await
-            .map_err(|e| format!("Failed to send compile task: {}", e))?;
+        let still_pending = match &task {
+            AssignedTask::Compile { .. } => matches!(job.state, JobState::Compiling),
+            AssignedTask::CheckerCompile { .. } => {
+                matches!(job.state, JobState::Compiling) && job.checker_binary.is_none()
+            }
+            AssignedTask::Batch { batch_id, .. } => {
+                matches!(job.state, JobState::Executing { .. })
+                    && !job.batch_results.contains_key(batch_id)
+                    && !job.undispatched_batches.contains(batch_id)
+            }
+        };
+        if !still_pending {
+            continue;
+        }
+
+        let attempts = job.dispatch_attempts.get(&task).copied().unwrap_or(0);
+        if attempts >= MAX_TASK_ATTEMPTS {
+            let error = match &task {
+                AssignedTask::Compile { .. } => format!(
+                    "Compilation lost after {} attempts: worker disconnected",
+                    attempts
+                ),
+                AssignedTask::CheckerCompile { .. } => format!(
+                    "Checker compilation lost after {} attempts: worker disconnected",
+                    attempts
+                ),
+                AssignedTask::Batch { batch_id, .. } => format!(
+                    "Batch {} lost after {} attempts: worker disconnected",
+                    batch_id, attempts
+                ),
+            };
+            warn!(job_id = %task.job_id(), error = %error, "Giving up on job");
+            job.fail(Verdict::JudgeError, error);
+            state.persist(&job);
+            continue;
+        }
 
         info!(
-            job_id = %job_id,
-            worker_id = %worker_id,
-            "Dispatched compile task"
+            worker_id = %lost_worker_id,
+            task = ?task,
+            "Re-queueing task from lost worker"
         );
 
-        Ok(())
-    } else {
-        Err(format!("Worker {} not found", worker_id))
+        match &task {
+            AssignedTask::Compile { .. } | AssignedTask::CheckerCompile { .. } => {
+                job.set_state(JobState::Queued);
+                state.persist(&job);
+            }
+            AssignedTask::Batch { batch_id, .. } => {
+                job.reset_live_results(batch_id);
+                job.undispatched_batches.push_front(batch_id.clone());
+            }
+        }
+
+        drop(job);
+        state.enqueue_front(task.job_id());
     }
 }
 
-/// Send an execute batch task to a specific worker
-pub async fn dispatch_execute_task(
+/// Run a batch again after its worker reported a system error for it, or fail
+/// its job once the batch has been dispatched `MAX_TASK_ATTEMPTS` times
+pub fn retry_failed_batch(state: &AppState, job_id: &str, batch_id: &str, error: &str) {
+    let Some(mut job) = state.jobs.get_mut(job_id) else {
+        return;
+    };
+    let still_pending = matches!(job.state, JobState::Executing { .. })
+        && job.batch_ids.iter().any(|id| id == batch_id)
+        && !job.batch_results.contains_key(batch_id)
+        && !job.undispatched_batches.iter().any(|id| id == batch_id);
+    if !still_pending {
+        return;
+    }
+
+    let task = AssignedTask::Batch {
+        job_id: job_id.to_string(),
+        batch_id: batch_id.to_string(),
+    };
+    let attempts = job.dispatch_attempts.get(&task).copied().unwrap_or(0);
+    if attempts >= MAX_TASK_ATTEMPTS {
+        let error = format!(
+            "Batch {} failed after {} attempts: {}",
+            batch_id, attempts, error
+        );
+        warn!(job_id = %job_id, error = %error, "Giving up on job");
+        job.fail(Verdict::JudgeError, error);
+        state.persist(&job);
+        return;
+    }
+
+    info!(job_id = %job_id, batch_id = %batch_id, "Re-queueing batch after system error");
+    job.reset_live_results(batch_id);
+    job.undispatched_batches.push_front(batch_id.to_string());
+    drop(job);
+    state.enqueue_front(job_id);
+}
+
+/// Stop a cancelled job's in-flight work: drop it from the queue, release its
+/// slots and tell every worker running part of it to abort.
+///
+/// Returns the number of workers notified.
+pub async fn cancel_job_tasks(state: &AppState, job_id: &str) -> usize {
+    state.queue.lock().unwrap().retain(|id| id != job_id);
+
+    let senders: Vec<_> = state
+        .workers
+        .iter_mut()
+        .filter_map(|mut entry| {
+            let before = entry.assigned.len();
+            entry.assigned.retain(|task, _| task.job_id() != job_id);
+            (entry.assigned.len() < before).then(|| (entry.key().clone(), entry.sender.clone()))
+        })
+        .collect();
+
+    for (worker_id, sender) in &senders {
+        let cmd = MasterCommand {
+            task: Some(master_command::Task::Cancel(CancelTask {
+                job_id: job_id.to_string(),
+            })),
+        };
+        if let Err(e) = sender.send(Ok(cmd)).await {
+            warn!(job_id = %job_id, worker_id = %worker_id, error = %e, "Failed to send cancel");
+        }
+    }
+
+    state.wake_dispatcher();
+    senders.len()
+}
+
+/// Load jobs from the store after a restart and queue the unfinished ones
+///
+/// Jobs that had not finished compiling start over. Executing jobs keep the
+/// batches already reported and only re-dispatch the rest, unless their
+/// binary (or their checker's) is missing, in which case they are compiled
+/// again.
+pub fn resume_jobs(state: &AppState) -> Result<(), String> {
+    let loaded_suites = suites::load(state)?;
+    let by_reference: HashMap<String, Arc<Suite>> = state
+        .suites
+        .iter()
+        .flat_map(|entry| entry.value().clone())
+        .map(|suite| (suite.reference(), suite))
+        .collect();
+
+    let jobs = state.store.load_jobs(&by_reference)?;
+    let mut resumed = 0;
+
+    // Jobs evicted before the restart still report as expired
+    let expired_ids = state.store.load_expired()?;
+    let expired = expired_ids.len();
+    {
+        let mut expired_jobs = state.expired.lock().unwrap();
+        for job_id in expired_ids {
+            expired_jobs.insert(job_id);
+        }
+    }
+
+    for mut job in jobs {
+        // Batch IDs are not stored; rebuild them so recorded results show up
+        if !job.batch_results.is_empty() {
+            job.batch_ids = (0..job.test_cases.len().div_ceil(BATCH_SIZE))
+                .map(batch_id)
+                .collect();
+            job.rebuild_results();
+        }
+
+        if !job.is_finished() {
+            let has_artifact = (job.binary.is_some() || is_interpreted(&job.language))
+                && (job.checker.is_none() || job.checker_binary.is_some());
+
+            if matches!(job.state, JobState::Executing { .. }) && has_artifact {
+                job.batch_ids = (0..job.test_cases.len().div_ceil(BATCH_SIZE))
+                    .map(batch_id)
+                    .collect();
+                job.undispatched_batches = job
+                    .batch_ids
+                    .iter()
+                    .filter(|id| !job.batch_results.contains_key(*id))
+                    .cloned()
+                    .collect();
+
+                if job.undispatched_batches.is_empty() {
+                    job.complete();
+                } else {
+                    job.state = JobState::Executing {
+                        pending_batches: job.undispatched_batches.len(),
+                    };
+                }
+            } else {
+                job.set_state(JobState::Queued);
+                job.binary = None;
+                job.checker_binary = None;
+                job.batch_results.clear();
+                job.results.clear();
+            }
+            state.persist(&job);
+        }
+
+        let job_id = job.id.clone();
+        let finished = job.is_finished();
+        if !finished && job.webhook.is_some() {
+            tokio::spawn(webhook::watch_job(
+                state.clone(),
+                job_id.clone(),
+                job.events.subscribe(),
+            ));
+        }
+        let redeliver = webhook::pending(&job);
+        state.jobs.insert(job_id.clone(), job);
+
+        // Completed before the restart, but its webhook never got through
+        if redeliver {
+            let state = state.clone();
+            let job_id = job_id.clone();
+            tokio::spawn(async move { webhook::deliver(&state, &job_id).await });
+        }
+
+        if !finished {
+            state.enqueue(&job_id);
+            resumed += 1;
+        }
+    }
+
+    info!(
+        jobs = state.jobs.len(),
+        resumed = resumed,
+        expired = expired,
+        suites = loaded_suites,
+        "Loaded jobs from store"
+    );
+    Ok(())
+}
+
+/// Dispatcher loop: places queued work whenever a worker slot is free
+pub async fn run_dispatcher(state: AppState) {
+    loop {
+        tokio::select! {
+            _ = state.dispatch_notify.notified() => {}
+            _ = tokio::time::sleep(DISPATCH_INTERVAL) => {}
+        }
+
+        dispatch_queued(&state).await;
+    }
+}
+
+/// What a queued job needs next
+enum NextStep {
+    /// Phase 1: needs a compile slot, for the checker first if it has one
+    Compile { language: String, checker: bool },
+    /// Phase 2: batches need execution slots (split into batches first if not yet started)
+    Execute {
+        prepare: bool,
+        language: String,
+        checker_language: Option<String>,
+        memory_mb: u64,
+    },
+    /// No connected worker will ever have `memory_mb` free for a batch
+    Unplaceable { memory_mb: u64 },
+    /// Nothing left to place; drop the job from the queue
+    Done,
+}
+
+/// Place queued work in queue order. A job that cannot be placed right now
+/// (e.g. no free worker for its language) keeps its position while later
+/// jobs are tried.
+async fn dispatch_queued(state: &AppState) {
+    let queued: Vec<String> = state.queue.lock().unwrap().iter().cloned().collect();
+
+    for job_id in queued {
+        let step = match state.jobs.get(&job_id) {
+            Some(job) => {
+                let checker_language = job.checker.as_ref().map(|c| c.language.as_str());
+                let memory_mb = batch_memory_mb(job.memory_limit_mb, job.checker.is_some());
+                let execute = |prepare| NextStep::Execute {
+                    prepare,
+                    language: job.language.clone(),
+                    checker_language: checker_language.map(str::to_string),
+                    memory_mb,
+                };
+                // With no workers connected the job waits for one to register
+                let unplaceable = !state.workers.is_empty()
+                    && !is_memory_supported(state, &job.language, checker_language, memory_mb);
+                let has_work = match job.state {
+                    JobState::Queued => true,
+                    JobState::Executing { .. } => !job.undispatched_batches.is_empty(),
+                    _ => false,
+                };
+                match (&job.state, &job.checker) {
+                    _ if has_work && unplaceable => NextStep::Unplaceable { memory_mb },
+                    (JobState::Queued, Some(checker)) if job.checker_binary.is_none() => {
+                        NextStep::Compile {
+                            language: checker.language.clone(),
+                            checker: true,
+                        }
+                    }
+                    (JobState::Queued, _)
+                        if job.binary.is_none() && !is_interpreted(&job.language) =>
+                    {
+                        NextStep::Compile {
+                            language: job.language.clone(),
+                            checker: false,
+                        }
+                    }
+                    (JobState::Queued, _) => execute(true),
+                    (JobState::Executing { .. }, _) if !job.undispatched_batches.is_empty() => {
+                        execute(false)
+                    }
+                    _ => NextStep::Done,
+                }
+            }
+            None => NextStep::Done,
+        };
+
+        let placed_all = match step {
+            NextStep::Done => true,
+            NextStep::Unplaceable { memory_mb } => {
+                if let Some(mut job) = state.jobs.get_mut(&job_id) {
+                    let error = format!(
+                        "No connected worker has {} MB of RAM to run this job's tests",
+                        memory_mb
+                    );
+                    warn!(job_id = %job_id, error = %error, "Giving up on job");
+                    job.fail(Verdict::JudgeError, error);
+                    state.persist(&job);
+                }
+                true
+            }
+            NextStep::Compile { language, checker } => {
+                let Some(worker_id) = select_compile_worker(state, &language) else {
+                    continue;
+                };
+
+                if let Some(mut job) = state.jobs.get_mut(&job_id) {
+                    job.set_state(JobState::Compiling);
+                    state.persist(&job);
+                }
+
+                if let Err(e) = dispatch_compile_task(state, &worker_id, &job_id, checker).await {
+                    warn!(job_id = %job_id, error = %e, "Failed to dispatch compile task");
+                    if let Some(mut job) = state.jobs.get_mut(&job_id) {
+                        if matches!(job.state, JobState::Compiling) {
+                            job.set_state(JobState::Queued);
+                            state.persist(&job);
+                        }
+                    }
+                    continue;
+                }
+                true
+            }
+            NextStep::Execute {
+                prepare,
+                language,
+                checker_language,
+                memory_mb,
+            } => {
+                if prepare {
+                    if let Some(mut job) = state.jobs.get_mut(&job_id) {
+                        prepare_execution(&mut job);
+                        state.persist(&job);
+                        info!(
+                            job_id = %job_id,
+                            batches = job.batch_ids.len(),
+                            "Dispatching execution phase"
+                        );
+                    }
+                }
+
+                dispatch_batches(
+                    state,
+                    &job_id,
+                    &language,
+                    checker_language.as_deref(),
+                    memory_mb,
+                )
+                .await
+            }
+        };
+
+        if placed_all {
+            state.queue.lock().unwrap().retain(|id| id != &job_id);
+        }
+    }
+}
+
+/// Place a job's undispatched batches on free slots, one at a time so that
+/// every placement sees the previous reservation.
+///
+/// Returns `true` once no batch is left waiting.
+async fn dispatch_batches(
     state: &AppState,
-    worker_id: &str,
     job_id: &str,
-    batch_id: &str,
     language: &str,
-    binary: Option<Vec<u8>>,
-    source_code: Option<String>,
-    test_cases: Vec<TestCase>,
-    time_limit_ms: u32,
-    memory_limit_mb: u32,
-) -> Result<(), String> {
-    if let Some(worker) = state.workers.get(worker_id) {
-        let payload = if let Some(bin) = binary {
-            Some(execute_batch_task::Payload::BinaryArtifact(bin))
-        } else if let Some(src) = source_code {
-            Some(execute_batch_task::Payload::SourceCode(src))
-        } else {
-            return Err("Neither binary nor source code provided".to_string());
+    checker_language: Option<&str>,
+    memory_mb: u64,
+) -> bool {
+    loop {
+        let Some(worker_id) = select_execution_worker(state, language, checker_language, memory_mb)
+        else {
+            return false;
         };
 
-        let cmd = MasterCommand {
-            task: Some(common::scheduler::master_command::Task::Execute(
-                ExecuteBatchTask {
-                    job_id: job_id.to_string(),
-                    batch_id: batch_id.to_string(),
-                    language: language.to_string(),
-                    payload,
-                    inputs: test_cases,
-                    time_limit_ms,
-                    memory_limit_mb,
-                },
-            )),
+        // Take the next batch off the waiting list before sending it, so a
+        // worker loss during the send re-queues it rather than skipping it
+        let task = {
+            let Some(mut job) = state.jobs.get_mut(job_id) else {
+                return true;
+            };
+            if !matches!(job.state, JobState::Executing { .. }) {
+                return true;
+            }
+            let Some(batch_id) = job.undispatched_batches.pop_front() else {
+                return true;
+            };
+            match batch_task(&job, &batch_id) {
+                Some(task) => task,
+                None => {
+                    // Skipping it would leave the job waiting on results that never come
+                    let error = format!("Batch {} has no test cases to execute", batch_id);
+                    warn!(job_id = %job_id, error = %error, "Giving up on job");
+                    job.fail(Verdict::JudgeError, error);
+                    state.persist(&job);
+                    return true;
+                }
+            }
         };
 
-        worker
-            .sender
-            .send(Ok(cmd))
-            .await
-            .map_err(|e| format!("Failed to send execute task: {}", e))?;
+        let batch_id = task.batch_id.clone();
+        if let Err(e) = dispatch_execute_task(state, &worker_id, task).await {
+            warn!(
+                job_id = %job_id,
+                batch_id = %batch_id,
+                worker_id = %worker_id,
+                error = %e,
+                "Failed to dispatch batch"
+            );
+            if let Some(mut job) = state.jobs.get_mut(job_id) {
+                job.undispatched_batches.push_front(batch_id);
+            }
+            return false;
+        }
+    }
+}
 
-        info!(
-            job_id = %job_id,
-            batch_id = %batch_id,
-            worker_id = %worker_id,
-            "Dispatched execute task"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::SqliteJobStore;
+
+    #[tokio::test]
+    async fn queued_job_fails_when_no_worker_has_enough_ram() {
+        let state = AppState::new(Arc::new(SqliteJobStore::open(":memory:").unwrap()));
+        state
+            .workers
+            .insert("w1".to_string(), WorkerInfo::idle(1024, &["cpp"]));
+
+        let job = JobContext::new(
+            "job".to_string(),
+            "cpp".to_string(),
+            "int main() {}".to_string(),
+            vec![],
+            Arc::new(vec![TestCase::default()]),
+            1000,
+            2048,
         );
+        state.jobs.insert(job.id.clone(), job);
+        state.enqueue("job");
 
-        Ok(())
-    } else {
-        Err(format!("Worker {} not found", worker_id))
+        dispatch_queued(&state).await;
+
+        let job = state.jobs.get("job").unwrap();
+        assert!(matches!(job.state, JobState::Completed));
+        assert!(job.error.as_deref().unwrap().contains("2048 MB"));
+        assert_eq!(state.queue_position("job"), None);
     }
 }
@@ -2,30 +2,102 @@
 //! 
 //! Provides thread-safe state containers for workers and jobs using DashMap.
 
-use common::scheduler::{MasterCommand, TestCaseResult};
+use crate::grading::{self, Grade, Subtask, Verdict};
+use crate::project::Project;
+use crate::store::{JobStore, StoreWrite, StoreWriter};
+use crate::suites::Suite;
+use crate::webhook::Webhook;
+use common::scheduler::{
+    execute_batch_task::Isolation, Comparator, MasterCommand, PoolStats, TestCaseResult,
+};
 use dashmap::DashMap;
-use std::sync::Arc;
-use tokio::sync::{mpsc, oneshot};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{broadcast, mpsc, oneshot, Notify};
 
 /// Final response sent back to HTTP client
 #[derive(Debug, Clone)]
 pub struct FinalResponse {
     pub job_id: String,
+    pub state: JobState,
     pub success: bool,
+    pub total_test_cases: usize,
     pub results: Vec<TestCaseResult>,
     pub compiler_output: Option<String>,
     pub error: Option<String>,
+    pub grade: Option<Grade>,
 }
 
 /// Current state of a job in the pipeline
 #[derive(Debug, Clone)]
 pub enum JobState {
+    /// Accepted, waiting in the master queue for a free worker
+    Queued,
     /// Phase 1: Waiting for compilation to complete
     Compiling,
     /// Phase 2: Executing test batches
     Executing { pending_batches: usize },
     /// Job completed (success or failure)
     Completed,
+    /// Job cancelled by the client before it completed
+    Cancelled,
+}
+
+/// Progress of a job, pushed to event stream subscribers
+#[derive(Debug, Clone)]
+pub enum JobEvent {
+    /// The job moved to a new state
+    State(JobState),
+    /// Phase 1 finished
+    CompileOutput { success: bool, output: String },
+    /// A test case finished
+    TestResult(TestCaseResult),
+    /// The job reached a final state; no more events follow
+    Finished {
+        state: JobState,
+        success: bool,
+        error: Option<String>,
+    },
+}
+
+/// Buffered events per job before slow subscribers start missing some
+const JOB_EVENT_CAPACITY: usize = 256;
+
+/// A unit of work that has been sent to a worker and not yet answered
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AssignedTask {
+    /// Phase 1 compile task for a job
+    Compile { job_id: String },
+    /// Phase 1 compile task for a job's checker
+    CheckerCompile { job_id: String },
+    /// Phase 2 execution batch for a job
+    Batch { job_id: String, batch_id: String },
+}
+
+impl AssignedTask {
+    pub fn job_id(&self) -> &str {
+        match self {
+            AssignedTask::Compile { job_id }
+            | AssignedTask::CheckerCompile { job_id }
+            | AssignedTask::Batch { job_id, .. } => job_id,
+        }
+    }
+}
+
+/// Source of a checker program (special judge) that judges a job's outputs,
+/// or of an interactor that talks to the submission while it runs
+#[derive(Debug, Clone)]
+pub struct CheckerSource {
+    /// Canonical name of a compiled language
+    pub language: String,
+    pub source_code: String,
+    pub compiler_flags: Vec<String>,
+    /// Run alongside the submission instead of after it
+    pub interactive: bool,
+    /// How long it may run per test
+    pub time_limit_ms: u32,
 }
 
 /// Context for an active job
@@ -33,22 +105,310 @@ pub struct JobContext {
     pub id: String,
     pub language: String,
     pub source_code: String,
+    /// Flags passed to the compiler in Phase 1
+    pub compiler_flags: Vec<String>,
     pub total_test_cases: usize,
-    /// Store results as they come in from various batches
+    /// Results of all finished batches, in original test order
     pub results: Vec<TestCaseResult>,
+    /// Batch IDs in original test order (populated when execution is dispatched)
+    pub batch_ids: Vec<String>,
+    /// Store results as they come in from various batches: BatchID -> results
+    pub batch_results: HashMap<String, Vec<TestCaseResult>>,
+    /// Results streamed in from batches that have not finished yet: BatchID -> results
+    pub live_results: HashMap<String, Vec<TestCaseResult>>,
+    /// Batches waiting for a free execution slot
+    pub undispatched_batches: VecDeque<String>,
     pub state: JobState,
     /// Compiled binary (populated after Phase 1)
     pub binary: Option<Vec<u8>>,
     /// Compiler output for display
     pub compiler_output: Option<String>,
+    /// Why the job failed, if it did
+    pub error: Option<String>,
     /// Channel to reply to the HTTP thread once done
     pub responder: Option<oneshot::Sender<FinalResponse>>,
-    /// Test cases for this job
-    pub test_cases: Vec<common::scheduler::TestCase>,
+    /// Source tree and build settings, if the submission is a project
+    /// (`source_code` is then empty)
+    pub project: Option<Project>,
+    /// Test cases for this job (shared with its suite, if it runs one)
+    pub test_cases: Arc<Vec<common::scheduler::TestCase>>,
+    /// Test suite the job's test cases come from
+    pub suite: Option<Arc<Suite>>,
     /// Time limit per test case in milliseconds
     pub time_limit_ms: u32,
     /// Memory limit per test case in MB
     pub memory_limit_mb: u32,
+    /// Whether a batch's tests share a container or each get a fresh one
+    pub isolation: Isolation,
+    /// How outputs are compared for tests without their own comparator
+    pub comparator: Option<Comparator>,
+    /// Checker or interactor that judges outputs instead of the comparators
+    pub checker: Option<CheckerSource>,
+    /// Groups of tests worth points; every test is worth one point if empty
+    pub subtasks: Vec<Subtask>,
+    /// Verdict and score, once the job has been graded
+    pub grade: Option<Grade>,
+    /// Compiled checker (populated in Phase 1, before the submission is compiled)
+    pub checker_binary: Option<Vec<u8>>,
+    /// Number of times each task of this job has been dispatched
+    pub dispatch_attempts: HashMap<AssignedTask, u32>,
+    /// When the job reached a final state (drives retention)
+    pub finished_at: Option<Instant>,
+    /// Progress events for `/jobs/:id/events` subscribers
+    pub events: broadcast::Sender<JobEvent>,
+    /// Where to report completion, if the client asked for it
+    pub webhook: Option<Webhook>,
+    /// Attempts made to deliver the completion webhook
+    pub webhook_deliveries: Vec<WebhookDelivery>,
+}
+
+/// One attempt at delivering a job's completion webhook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub attempt: u32,
+    pub at_unix_ms: u64,
+    /// HTTP status of the response, if one was received
+    pub status_code: Option<u16>,
+    /// Why the attempt failed, if it did
+    pub error: Option<String>,
+}
+
+impl JobContext {
+    /// Create a job in the `Queued` state from its submission
+    pub fn new(
+        id: String,
+        language: String,
+        source_code: String,
+        compiler_flags: Vec<String>,
+        test_cases: Arc<Vec<common::scheduler::TestCase>>,
+        time_limit_ms: u32,
+        memory_limit_mb: u32,
+    ) -> Self {
+        Self {
+            id,
+            language,
+            source_code,
+            compiler_flags,
+            total_test_cases: test_cases.len(),
+            results: vec![],
+            batch_ids: vec![],
+            batch_results: HashMap::new(),
+            live_results: HashMap::new(),
+            undispatched_batches: VecDeque::new(),
+            state: JobState::Queued,
+            binary: None,
+            compiler_output: None,
+            error: None,
+            responder: None,
+            project: None,
+            test_cases,
+            suite: None,
+            time_limit_ms,
+            memory_limit_mb,
+            isolation: Isolation::Shared,
+            comparator: None,
+            checker: None,
+            checker_binary: None,
+            subtasks: vec![],
+            grade: None,
+            dispatch_attempts: HashMap::new(),
+            finished_at: None,
+            events: broadcast::channel(JOB_EVENT_CAPACITY).0,
+            webhook: None,
+            webhook_deliveries: vec![],
+        }
+    }
+
+    /// Move the job to a new state and notify subscribers
+    pub fn set_state(&mut self, state: JobState) {
+        self.state = state.clone();
+        self.emit(JobEvent::State(state));
+    }
+
+    /// Notify subscribers; events are dropped when nobody listens
+    pub fn emit(&self, event: JobEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Append a test result streamed from a batch that is still running
+    ///
+    /// Returns `false` if the batch is unknown or already finished.
+    pub fn record_live_result(&mut self, batch_id: &str, result: TestCaseResult) -> bool {
+        if !self.batch_ids.iter().any(|id| id == batch_id)
+            || self.batch_results.contains_key(batch_id)
+        {
+            return false;
+        }
+
+        self.live_results
+            .entry(batch_id.to_string())
+            .or_default()
+            .push(result.clone());
+        self.rebuild_results();
+        self.emit(JobEvent::TestResult(result));
+        true
+    }
+
+    /// Store the results of a batch and rebuild `results` in original test order.
+    ///
+    /// Returns `false` if the batch is unknown or its results were already recorded.
+    pub fn record_batch(&mut self, batch_id: &str, results: Vec<TestCaseResult>) -> bool {
+        if !self.batch_ids.iter().any(|id| id == batch_id)
+            || self.batch_results.contains_key(batch_id)
+        {
+            return false;
+        }
+
+        // Announce the results that were not streamed while the batch ran
+        let streamed = self.live_results.remove(batch_id).map_or(0, |r| r.len());
+        for result in results.iter().skip(streamed) {
+            self.emit(JobEvent::TestResult(result.clone()));
+        }
+
+        self.batch_results.insert(batch_id.to_string(), results);
+        self.undispatched_batches.retain(|id| id != batch_id);
+        self.rebuild_results();
+        true
+    }
+
+    /// Forget what a batch streamed before it was lost, so it can run again
+    pub fn reset_live_results(&mut self, batch_id: &str) {
+        if self.live_results.remove(batch_id).is_some() {
+            self.rebuild_results();
+        }
+    }
+
+    /// Rebuild `results` from finished and running batches in original test order
+    pub fn rebuild_results(&mut self) {
+        self.results = self
+            .batch_ids
+            .iter()
+            .filter_map(|id| {
+                self.batch_results
+                    .get(id)
+                    .or_else(|| self.live_results.get(id))
+            })
+            .flatten()
+            .cloned()
+            .collect();
+    }
+
+    /// Whether the job has reached a final state
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, JobState::Completed | JobState::Cancelled)
+    }
+
+    /// Grade the job's results and mark it as completed; it succeeds if
+    /// every test passed
+    pub fn complete(&mut self) {
+        let grade = grading::grade(&self.test_cases, &self.results, &self.subtasks);
+        let success = grade.verdict == Verdict::Accepted;
+        self.grade = Some(grade);
+        self.finish(success, None);
+    }
+
+    /// Mark the job as completed without running its tests, scoring nothing
+    pub fn fail(&mut self, verdict: Verdict, error: String) {
+        self.grade = Some(grading::failed(verdict, &self.test_cases, &self.subtasks));
+        self.finish(false, Some(error));
+    }
+
+    /// Mark the job as completed and reply to the waiting HTTP thread
+    pub fn finish(&mut self, success: bool, error: Option<String>) {
+        self.state = JobState::Completed;
+        self.error = error;
+        self.respond(success);
+    }
+
+    /// Mark the job as cancelled and reply to the waiting HTTP thread
+    pub fn cancel(&mut self) {
+        self.state = JobState::Cancelled;
+        self.error = Some("Job cancelled".to_string());
+        self.respond(false);
+    }
+
+    /// Approximate heap footprint of the job in bytes
+    ///
+    /// Test cases of a suite are shared between jobs and not counted.
+    pub fn approx_bytes(&self) -> usize {
+        let test_cases: usize = match self.suite {
+            Some(_) => 0,
+            None => self
+                .test_cases
+                .iter()
+                .map(|tc| tc.id.len() + tc.input.len() + tc.expected_output.len())
+                .sum(),
+        };
+        // `results` holds every finished and live batch's results in order
+        let results: usize = self
+            .results
+            .iter()
+            .map(|r| {
+                r.test_id.len()
+                    + r.status.len()
+                    + r.stdout.len()
+                    + r.stderr.len()
+                    + r.checker_message.len()
+            })
+            .sum();
+        let checker = self.checker.as_ref().map_or(0, |c| c.source_code.len());
+
+        self.source_code.len()
+            + self.project.as_ref().map_or(0, Project::size)
+            + test_cases
+            + results
+            + checker
+            + self.binary.as_ref().map_or(0, Vec::len)
+            + self.checker_binary.as_ref().map_or(0, Vec::len)
+            + self.compiler_output.as_ref().map_or(0, String::len)
+    }
+
+    /// Release what a finished job no longer needs and reply to the HTTP thread
+    fn respond(&mut self, success: bool) {
+        self.finished_at = Some(Instant::now());
+        self.binary = None;
+        self.checker_binary = None;
+        self.undispatched_batches.clear();
+        self.dispatch_attempts.clear();
+        self.live_results.clear();
+
+        self.emit(JobEvent::Finished {
+            state: self.state.clone(),
+            success,
+            error: self.error.clone(),
+        });
+
+        if let Some(responder) = self.responder.take() {
+            let _ = responder.send(FinalResponse {
+                job_id: self.id.clone(),
+                state: self.state.clone(),
+                success,
+                total_test_cases: self.total_test_cases,
+                results: self.results.clone(),
+                compiler_output: self.compiler_output.clone(),
+                error: self.error.clone(),
+                grade: self.grade.clone(),
+            });
+        }
+    }
+}
+
+/// Liveness of a worker, derived from its heartbeats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerLiveness {
+    /// Heartbeats arriving on time
+    Alive,
+    /// Missed too many heartbeats; not scheduled until one arrives again
+    Suspect,
+}
+
+impl WorkerLiveness {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerLiveness::Alive => "alive",
+            WorkerLiveness::Suspect => "suspect",
+        }
+    }
 }
 
 /// Worker connection info
@@ -61,14 +421,120 @@ pub struct WorkerInfo {
     pub total_ram_mb: u64,
     /// Worker capabilities (e.g., "can_compile", "high_memory")
     pub tags: Vec<String>,
+    /// Languages (canonical names) this worker has a build image for
+    pub compile_languages: HashSet<String>,
+    /// Languages (canonical names) this worker has a runner image for
+    pub run_languages: HashSet<String>,
     /// Last known CPU load percentage
     pub cpu_load_percent: f32,
     /// Last known RAM usage in MB
     pub ram_usage_mb: u64,
     /// Number of active tasks on this worker
     pub active_tasks: u32,
+    /// Warm container pools, as of the last heartbeat
+    pub pools: Vec<PoolStats>,
+    /// Number of tasks this worker runs concurrently
+    pub execution_slots: u32,
+    /// Tasks dispatched to this worker that have not reported back yet,
+    /// each holding one slot and the memory (MB) reserved for it
+    pub assigned: HashMap<AssignedTask, u64>,
+    /// When the last heartbeat (or the registration) was received
+    pub last_heartbeat: Instant,
+    /// Liveness derived from `last_heartbeat`
+    pub liveness: WorkerLiveness,
+}
+
+impl WorkerInfo {
+    /// Whether new work may be placed on this worker
+    pub fn is_schedulable(&self) -> bool {
+        self.liveness == WorkerLiveness::Alive
+    }
+
+    /// Slots not held by an in-flight task
+    pub fn free_slots(&self) -> u32 {
+        self.execution_slots
+            .saturating_sub(self.assigned.len() as u32)
+    }
+
+    /// Memory reserved by in-flight tasks in MB
+    pub fn reserved_ram_mb(&self) -> u64 {
+        self.assigned.values().sum()
+    }
+
+    /// RAM in MB that a new task may use: the lower of what the last heartbeat
+    /// reported as free and what is not already reserved
+    pub fn free_ram_mb(&self) -> u64 {
+        self.total_ram_mb
+            .saturating_sub(self.ram_usage_mb.max(self.reserved_ram_mb()))
+    }
+
+    /// Whether a task needing `memory_mb` fits on this worker right now
+    pub fn can_fit(&self, memory_mb: u64) -> bool {
+        self.is_schedulable() && self.free_slots() > 0 && self.free_ram_mb() >= memory_mb
+    }
+
+    /// An idle, live worker that compiles and runs `languages`
+    #[cfg(test)]
+    pub fn idle(total_ram_mb: u64, languages: &[&str]) -> Self {
+        let languages: HashSet<String> = languages.iter().map(|l| l.to_string()).collect();
+        Self {
+            sender: mpsc::channel(16).0,
+            cpu_cores: 4,
+            total_ram_mb,
+            tags: vec![],
+            compile_languages: languages.clone(),
+            run_languages: languages,
+            cpu_load_percent: 0.0,
+            ram_usage_mb: 0,
+            active_tasks: 0,
+            pools: vec![],
+            execution_slots: 4,
+            assigned: HashMap::new(),
+            last_heartbeat: Instant::now(),
+            liveness: WorkerLiveness::Alive,
+        }
+    }
 }
 
+/// IDs of jobs evicted by retention, remembered (up to a cap) so that status
+/// lookups can tell "expired" apart from "never existed"
+pub struct ExpiredJobs {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl ExpiredJobs {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Remember an evicted job, forgetting the oldest one beyond capacity
+    pub fn insert(&mut self, job_id: String) {
+        if !self.ids.insert(job_id.clone()) {
+            return;
+        }
+        self.order.push_back(job_id);
+
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn contains(&self, job_id: &str) -> bool {
+        self.ids.contains(job_id)
+    }
+}
+
+/// Maximum number of evicted job IDs remembered as expired
+pub const EXPIRED_JOBS_CAPACITY: usize = 100_000;
+
 /// Application-wide shared state
 #[derive(Clone)]
 pub struct AppState {
@@ -76,19 +542,114 @@ pub struct AppState {
     pub workers: Arc<DashMap<String, WorkerInfo>>,
     /// Active jobs: JobID -> JobContext
     pub jobs: Arc<DashMap<String, JobContext>>,
+    /// Jobs waiting for a free worker slot (to start, or to place more batches),
+    /// in dispatch order
+    pub queue: Arc<Mutex<VecDeque<String>>>,
+    /// Wakes the dispatcher loop when a job is queued or a worker frees up
+    pub dispatch_notify: Arc<Notify>,
+    /// Jobs removed by the retention sweeper, also recorded in `store`
+    pub expired: Arc<Mutex<ExpiredJobs>>,
+    /// Durable copy of every job
+    pub store: Arc<dyn JobStore>,
+    /// Applies job transitions to `store` off the async threads
+    pub writer: StoreWriter,
+    /// Registered test suites: name -> versions, oldest first
+    pub suites: Arc<DashMap<String, Vec<Arc<Suite>>>>,
+    /// Held while a suite upload is stored, so concurrent uploads of the same
+    /// name cannot claim the same version
+    pub suite_uploads: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(store: Arc<dyn JobStore>) -> Self {
         Self {
             workers: Arc::new(DashMap::new()),
             jobs: Arc::new(DashMap::new()),
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+            dispatch_notify: Arc::new(Notify::new()),
+            expired: Arc::new(Mutex::new(ExpiredJobs::new(EXPIRED_JOBS_CAPACITY))),
+            writer: StoreWriter::spawn(store.clone()),
+            store,
+            suites: Arc::new(DashMap::new()),
+            suite_uploads: Arc::new(tokio::sync::Mutex::new(())),
         }
     }
-}
 
-impl Default for AppState {
-    fn default() -> Self {
-        Self::new()
+    /// Write a job's current state through to the store
+    pub fn persist(&self, job: &JobContext) {
+        self.writer.send(StoreWrite::Update(job.into()));
+    }
+
+    /// Write a freshly compiled binary (or checker binary) through to the store
+    pub fn persist_binary(&self, job_id: &str, checker: bool, binary: Vec<u8>) {
+        self.writer.send(StoreWrite::Binary {
+            job_id: job_id.to_string(),
+            checker,
+            binary,
+        });
+    }
+
+    /// Write a job's webhook delivery attempts through to the store
+    pub fn persist_deliveries(&self, job: &JobContext) {
+        self.writer.send(StoreWrite::Deliveries {
+            job_id: job.id.clone(),
+            deliveries: job.webhook_deliveries.clone(),
+        });
+    }
+
+    /// Write a finished batch's results through to the store
+    pub fn persist_batch(&self, job_id: &str, batch_id: &str, results: &[TestCaseResult]) {
+        self.writer.send(StoreWrite::Batch {
+            job_id: job_id.to_string(),
+            batch_id: batch_id.to_string(),
+            results: results.to_vec(),
+        });
+    }
+
+    /// Add a job at the back of the queue and wake the dispatcher.
+    ///
+    /// Returns the job's 1-based queue position.
+    pub fn enqueue(&self, job_id: &str) -> usize {
+        let position = {
+            let mut queue = self.queue.lock().unwrap();
+            if let Some(i) = queue.iter().position(|id| id == job_id) {
+                i + 1
+            } else {
+                queue.push_back(job_id.to_string());
+                queue.len()
+            }
+        };
+        self.wake_dispatcher();
+        position
+    }
+
+    /// Move a job to the front of the queue (e.g. a started job with work to re-place)
+    pub fn enqueue_front(&self, job_id: &str) {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            queue.retain(|id| id != job_id);
+            queue.push_front(job_id.to_string());
+        }
+        self.wake_dispatcher();
+    }
+
+    /// 1-based position of a job in the queue, if it is queued
+    pub fn queue_position(&self, job_id: &str) -> Option<usize> {
+        self.queue
+            .lock()
+            .unwrap()
+            .iter()
+            .position(|id| id == job_id)
+            .map(|i| i + 1)
+    }
+
+    /// Whether a job was evicted by retention
+    pub fn is_expired(&self, job_id: &str) -> bool {
+        self.expired.lock().unwrap().contains(job_id)
+    }
+
+    /// Signal the dispatcher that a job or a worker slot may be available
+    pub fn wake_dispatcher(&self) {
+        self.dispatch_notify.notify_one();
     }
 }
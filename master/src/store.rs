@@ -0,0 +1,876 @@
+//! Master Node - Job Store
+//!
+//! Persists submissions, test suites, state transitions and batch results so
+//! that jobs survive a master restart. `AppState` keeps the live copy of every
+//! job in memory and writes through to a `JobStore`: transitions go through a
+//! `StoreWriter`, whose thread applies them in order, so neither async tasks
+//! nor holders of a job's lock wait on the database.
+
+use crate::grading::{Grade, Subtask};
+use crate::project::Project;
+use crate::state::{CheckerSource, JobContext, JobState, WebhookDelivery, EXPIRED_JOBS_CAPACITY};
+use crate::suites::Suite;
+use crate::webhook::Webhook;
+use common::scheduler::{
+    execute_batch_task::Isolation, Comparator, SourceFile, TestCase, TestCaseResult,
+};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Durable storage for jobs
+pub trait JobStore: Send + Sync {
+    /// Record a newly submitted job
+    fn insert_job(&self, job: &JobContext) -> Result<(), String>;
+
+    /// Record a job's current state, error, compiler output and grade; a
+    /// finished job's binaries are dropped
+    fn update_job(&self, record: &JobRecord) -> Result<(), String>;
+
+    /// Record a job's compiled binary, or its checker's
+    fn insert_binary(&self, job_id: &str, checker: bool, binary: &[u8]) -> Result<(), String>;
+
+    /// Record every attempt made so far to deliver a job's webhook
+    fn update_deliveries(&self, job_id: &str, deliveries: &[WebhookDelivery])
+        -> Result<(), String>;
+
+    /// Record the results of one finished batch
+    fn insert_batch(
+        &self,
+        job_id: &str,
+        batch_id: &str,
+        results: &[TestCaseResult],
+    ) -> Result<(), String>;
+
+    /// Remove an evicted job and its results, remembering its ID as expired
+    fn expire_job(&self, job_id: &str) -> Result<(), String>;
+
+    /// IDs of evicted jobs still remembered as expired, oldest first
+    fn load_expired(&self) -> Result<Vec<String>, String>;
+
+    /// Load every stored job in submission order, with its recorded batch
+    /// results; `suites` maps `name@version` to the suites jobs may reference.
+    /// Jobs that cannot be loaded (e.g. a corrupt row) are logged and skipped.
+    fn load_jobs(&self, suites: &HashMap<String, Arc<Suite>>) -> Result<Vec<JobContext>, String>;
+
+    /// Record a new version of a test suite
+    fn insert_suite(&self, suite: &Suite) -> Result<(), String>;
+
+    /// Load every stored suite, ordered by name and version
+    fn load_suites(&self) -> Result<Vec<Suite>, String>;
+}
+
+/// Columns of a job that change as it moves through the pipeline
+pub struct JobRecord {
+    pub id: String,
+    pub state: JobState,
+    pub error: Option<String>,
+    pub compiler_output: Option<String>,
+    pub finished_at: Option<Instant>,
+    pub grade: Option<Grade>,
+}
+
+impl From<&JobContext> for JobRecord {
+    fn from(job: &JobContext) -> Self {
+        Self {
+            id: job.id.clone(),
+            state: job.state.clone(),
+            error: job.error.clone(),
+            compiler_output: job.compiler_output.clone(),
+            finished_at: job.finished_at,
+            grade: job.grade.clone(),
+        }
+    }
+}
+
+/// A write for the store's writer thread
+pub enum StoreWrite {
+    /// A job's transition
+    Update(JobRecord),
+    /// A binary, written once when it is compiled
+    Binary {
+        job_id: String,
+        checker: bool,
+        binary: Vec<u8>,
+    },
+    /// Attempts to deliver a job's webhook, all of them
+    Deliveries {
+        job_id: String,
+        deliveries: Vec<WebhookDelivery>,
+    },
+    /// A finished batch's results
+    Batch {
+        job_id: String,
+        batch_id: String,
+        results: Vec<TestCaseResult>,
+    },
+    /// An evicted job
+    Expire { job_id: String },
+}
+
+/// Hands writes to a thread that applies them to a `JobStore` in the order
+/// they were sent; failures are logged there
+#[derive(Clone)]
+pub struct StoreWriter {
+    sender: mpsc::Sender<StoreWrite>,
+}
+
+impl StoreWriter {
+    pub fn spawn(store: Arc<dyn JobStore>) -> Self {
+        let (sender, receiver) = mpsc::channel::<StoreWrite>();
+        thread::Builder::new()
+            .name("job-store-writer".to_string())
+            .spawn(move || {
+                for write in receiver {
+                    apply(store.as_ref(), write);
+                }
+            })
+            .expect("Failed to start job store writer");
+        Self { sender }
+    }
+
+    pub fn send(&self, write: StoreWrite) {
+        if self.sender.send(write).is_err() {
+            warn!("Job store writer has stopped; dropping write");
+        }
+    }
+}
+
+fn apply(store: &dyn JobStore, write: StoreWrite) {
+    match write {
+        StoreWrite::Update(record) => {
+            if let Err(e) = store.update_job(&record) {
+                warn!(job_id = %record.id, error = %e, "Failed to persist job");
+            }
+        }
+        StoreWrite::Binary {
+            job_id,
+            checker,
+            binary,
+        } => {
+            if let Err(e) = store.insert_binary(&job_id, checker, &binary) {
+                warn!(job_id = %job_id, checker, error = %e, "Failed to persist binary");
+            }
+        }
+        StoreWrite::Deliveries { job_id, deliveries } => {
+            if let Err(e) = store.update_deliveries(&job_id, &deliveries) {
+                warn!(job_id = %job_id, error = %e, "Failed to persist webhook deliveries");
+            }
+        }
+        StoreWrite::Batch {
+            job_id,
+            batch_id,
+            results,
+        } => {
+            if let Err(e) = store.insert_batch(&job_id, &batch_id, &results) {
+                warn!(job_id = %job_id, batch_id = %batch_id, error = %e, "Failed to persist batch");
+            }
+        }
+        StoreWrite::Expire { job_id } => {
+            if let Err(e) = store.expire_job(&job_id) {
+                warn!(job_id = %job_id, error = %e, "Failed to delete evicted job from store");
+            }
+        }
+    }
+}
+
+/// Submission fields of a job, stored as JSON
+#[derive(Serialize, Deserialize)]
+struct StoredSpec {
+    language: String,
+    source_code: String,
+    compiler_flags: Vec<String>,
+    project: Option<StoredProject>,
+    /// Empty when the job runs a suite
+    test_cases: Vec<StoredTestCase>,
+    /// `name@version` of the job's test suite
+    suite: Option<String>,
+    time_limit_ms: u32,
+    memory_limit_mb: u32,
+    isolation: i32,
+    callback_url: Option<String>,
+    callback_secret: Option<String>,
+    comparator: Option<StoredComparator>,
+    checker: Option<StoredChecker>,
+    subtasks: Vec<Subtask>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredChecker {
+    language: String,
+    source_code: String,
+    compiler_flags: Vec<String>,
+    interactive: bool,
+    time_limit_ms: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProject {
+    files: Vec<StoredFile>,
+    build_command: Option<String>,
+    artifact: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredFile {
+    path: String,
+    #[serde(with = "base64_bytes")]
+    content: Vec<u8>,
+}
+
+impl From<&Project> for StoredProject {
+    fn from(p: &Project) -> Self {
+        Self {
+            files: p
+                .files
+                .iter()
+                .map(|f| StoredFile {
+                    path: f.path.clone(),
+                    content: f.content.clone(),
+                })
+                .collect(),
+            build_command: p.build_command.clone(),
+            artifact: p.artifact.clone(),
+        }
+    }
+}
+
+impl From<StoredProject> for Project {
+    fn from(p: StoredProject) -> Self {
+        Self {
+            files: p
+                .files
+                .into_iter()
+                .map(|f| SourceFile {
+                    path: f.path,
+                    content: f.content,
+                })
+                .collect(),
+            build_command: p.build_command,
+            artifact: p.artifact,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredTestCase {
+    id: String,
+    #[serde(with = "base64_bytes")]
+    input: Vec<u8>,
+    expected_output: String,
+    comparator: Option<StoredComparator>,
+}
+
+impl From<&TestCase> for StoredTestCase {
+    fn from(tc: &TestCase) -> Self {
+        Self {
+            id: tc.id.clone(),
+            input: tc.input.clone(),
+            expected_output: tc.expected_output.clone(),
+            comparator: tc.comparator.as_ref().map(Into::into),
+        }
+    }
+}
+
+impl From<StoredTestCase> for TestCase {
+    fn from(tc: StoredTestCase) -> Self {
+        Self {
+            id: tc.id,
+            input: tc.input,
+            expected_output: tc.expected_output,
+            comparator: tc.comparator.map(Into::into),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredComparator {
+    mode: i32,
+    abs_epsilon: f64,
+    rel_epsilon: f64,
+}
+
+impl From<&Comparator> for StoredComparator {
+    fn from(c: &Comparator) -> Self {
+        Self {
+            mode: c.mode,
+            abs_epsilon: c.abs_epsilon,
+            rel_epsilon: c.rel_epsilon,
+        }
+    }
+}
+
+impl From<StoredComparator> for Comparator {
+    fn from(c: StoredComparator) -> Self {
+        Self {
+            mode: c.mode,
+            abs_epsilon: c.abs_epsilon,
+            rel_epsilon: c.rel_epsilon,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredResult {
+    test_id: String,
+    status: String,
+    #[serde(with = "base64_bytes")]
+    stdout: Vec<u8>,
+    stderr: String,
+    time_ms: i32,
+    memory_bytes: i32,
+    score: f64,
+    checker_message: String,
+}
+
+impl From<&JobContext> for StoredSpec {
+    fn from(job: &JobContext) -> Self {
+        Self {
+            language: job.language.clone(),
+            source_code: job.source_code.clone(),
+            compiler_flags: job.compiler_flags.clone(),
+            project: job.project.as_ref().map(Into::into),
+            test_cases: match job.suite {
+                Some(_) => vec![],
+                None => job.test_cases.iter().map(Into::into).collect(),
+            },
+            suite: job.suite.as_ref().map(|suite| suite.reference()),
+            time_limit_ms: job.time_limit_ms,
+            memory_limit_mb: job.memory_limit_mb,
+            isolation: job.isolation as i32,
+            callback_url: job.webhook.as_ref().map(|w| w.url.clone()),
+            callback_secret: job.webhook.as_ref().and_then(|w| w.secret.clone()),
+            comparator: job.comparator.as_ref().map(Into::into),
+            checker: job.checker.as_ref().map(|c| StoredChecker {
+                language: c.language.clone(),
+                source_code: c.source_code.clone(),
+                compiler_flags: c.compiler_flags.clone(),
+                interactive: c.interactive,
+                time_limit_ms: c.time_limit_ms,
+            }),
+            subtasks: job.subtasks.clone(),
+        }
+    }
+}
+
+impl From<&TestCaseResult> for StoredResult {
+    fn from(r: &TestCaseResult) -> Self {
+        Self {
+            test_id: r.test_id.clone(),
+            status: r.status.clone(),
+            stdout: r.stdout.clone(),
+            stderr: r.stderr.clone(),
+            time_ms: r.time_ms,
+            memory_bytes: r.memory_bytes,
+            score: r.score,
+            checker_message: r.checker_message.clone(),
+        }
+    }
+}
+
+impl From<StoredResult> for TestCaseResult {
+    fn from(r: StoredResult) -> Self {
+        Self {
+            test_id: r.test_id,
+            status: r.status,
+            stdout: r.stdout,
+            stderr: r.stderr,
+            time_ms: r.time_ms,
+            memory_bytes: r.memory_bytes,
+            score: r.score,
+            checker_message: r.checker_message,
+        }
+    }
+}
+
+/// Serde for raw bytes (stdin, stdout, project files) as a base64 string
+mod base64_bytes {
+    use base64::Engine;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(de::Error::custom)
+    }
+}
+
+fn state_to_str(state: &JobState) -> &'static str {
+    match state {
+        JobState::Queued => "queued",
+        JobState::Compiling => "compiling",
+        JobState::Executing { .. } => "executing",
+        JobState::Completed => "completed",
+        JobState::Cancelled => "cancelled",
+    }
+}
+
+/// Parse a stored state; batch progress of an executing job is rebuilt on resume
+fn state_from_str(state: &str) -> Result<JobState, String> {
+    match state {
+        "queued" => Ok(JobState::Queued),
+        "compiling" => Ok(JobState::Compiling),
+        "executing" => Ok(JobState::Executing { pending_batches: 0 }),
+        "completed" => Ok(JobState::Completed),
+        "cancelled" => Ok(JobState::Cancelled),
+        other => Err(format!("Unknown job state: {}", other)),
+    }
+}
+
+fn to_unix_ms(instant: Instant) -> i64 {
+    let at = SystemTime::now() - instant.elapsed();
+    at.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+fn from_unix_ms(ms: i64) -> Instant {
+    let at = UNIX_EPOCH + Duration::from_millis(ms.max(0) as u64);
+    let ago = SystemTime::now().duration_since(at).unwrap_or_default();
+    Instant::now().checked_sub(ago).unwrap_or_else(Instant::now)
+}
+
+/// Columns of a stored job besides its ID, as read back on load
+struct JobRow {
+    spec: String,
+    state: String,
+    error: Option<String>,
+    compiler_output: Option<String>,
+    binary: Option<Vec<u8>>,
+    finished_at_ms: Option<i64>,
+    checker_binary: Option<Vec<u8>>,
+    grade: Option<String>,
+    deliveries: Option<String>,
+}
+
+/// `JobStore` backed by an embedded SQLite database
+pub struct SqliteJobStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteJobStore {
+    /// Open (or create) the database at `path`
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = Connection::open(path)
+            .map_err(|e| format!("Failed to open job store {}: {}", path, e))?;
+
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS jobs (
+                 id TEXT PRIMARY KEY,
+                 spec TEXT NOT NULL,
+                 state TEXT NOT NULL,
+                 error TEXT,
+                 compiler_output TEXT,
+                 binary BLOB,
+                 checker_binary BLOB,
+                 grade TEXT,
+                 webhook_deliveries TEXT,
+                 finished_at_ms INTEGER
+             );
+             CREATE TABLE IF NOT EXISTS batch_results (
+                 job_id TEXT NOT NULL,
+                 batch_id TEXT NOT NULL,
+                 results TEXT NOT NULL,
+                 PRIMARY KEY (job_id, batch_id)
+             );
+             CREATE TABLE IF NOT EXISTS suites (
+                 name TEXT NOT NULL,
+                 version INTEGER NOT NULL,
+                 hash TEXT NOT NULL,
+                 test_cases TEXT NOT NULL,
+                 PRIMARY KEY (name, version)
+             );
+             CREATE TABLE IF NOT EXISTS expired_jobs (
+                 id TEXT PRIMARY KEY
+             );",
+        )
+        .map_err(|e| format!("Failed to initialise job store: {}", e))?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn load_batches(
+        conn: &Connection,
+        job_id: &str,
+    ) -> Result<HashMap<String, Vec<TestCaseResult>>, String> {
+        let mut stmt = conn
+            .prepare_cached("SELECT batch_id, results FROM batch_results WHERE job_id = ?1")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![job_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut batches = HashMap::new();
+        for row in rows {
+            let (batch_id, results) = row.map_err(|e| e.to_string())?;
+            let results: Vec<StoredResult> =
+                serde_json::from_str(&results).map_err(|e| e.to_string())?;
+            batches.insert(batch_id, results.into_iter().map(Into::into).collect());
+        }
+        Ok(batches)
+    }
+
+    /// Rebuild a job from its stored row and batch results
+    fn load_job(
+        conn: &Connection,
+        suites: &HashMap<String, Arc<Suite>>,
+        id: String,
+        row: JobRow,
+    ) -> Result<JobContext, String> {
+        let spec: StoredSpec =
+            serde_json::from_str(&row.spec).map_err(|e| format!("Corrupt spec: {}", e))?;
+        let grade = row
+            .grade
+            .map(|grade| serde_json::from_str(&grade))
+            .transpose()
+            .map_err(|e| format!("Corrupt grade: {}", e))?;
+        let deliveries = row
+            .deliveries
+            .map(|deliveries| serde_json::from_str(&deliveries))
+            .transpose()
+            .map_err(|e| format!("Corrupt webhook deliveries: {}", e))?;
+
+        let suite = spec
+            .suite
+            .map(|reference| {
+                suites
+                    .get(&reference)
+                    .cloned()
+                    .ok_or_else(|| format!("Unknown suite {}", reference))
+            })
+            .transpose()?;
+        let test_cases = match &suite {
+            Some(suite) => suite.test_cases.clone(),
+            None => Arc::new(spec.test_cases.into_iter().map(Into::into).collect()),
+        };
+
+        let mut job = JobContext::new(
+            id,
+            spec.language,
+            spec.source_code,
+            spec.compiler_flags,
+            test_cases,
+            spec.time_limit_ms,
+            spec.memory_limit_mb,
+        );
+        job.project = spec.project.map(Into::into);
+        job.suite = suite;
+        job.isolation = Isolation::try_from(spec.isolation).unwrap_or_default();
+        job.comparator = spec.comparator.map(Into::into);
+        job.checker = spec.checker.map(|c| CheckerSource {
+            language: c.language,
+            source_code: c.source_code,
+            compiler_flags: c.compiler_flags,
+            interactive: c.interactive,
+            time_limit_ms: c.time_limit_ms,
+        });
+        job.subtasks = spec.subtasks;
+        job.webhook = spec.callback_url.map(|url| Webhook {
+            url,
+            secret: spec.callback_secret,
+        });
+        job.state = state_from_str(&row.state)?;
+        job.error = row.error;
+        job.compiler_output = row.compiler_output;
+        job.binary = row.binary;
+        job.checker_binary = row.checker_binary;
+        job.grade = grade;
+        job.webhook_deliveries = deliveries.unwrap_or_default();
+        job.finished_at = row.finished_at_ms.map(from_unix_ms);
+        job.batch_results = Self::load_batches(conn, &job.id)?;
+        Ok(job)
+    }
+}
+
+impl JobStore for SqliteJobStore {
+    fn insert_job(&self, job: &JobContext) -> Result<(), String> {
+        let spec = serde_json::to_string(&StoredSpec::from(job)).map_err(|e| e.to_string())?;
+
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO jobs (id, spec, state) VALUES (?1, ?2, ?3)",
+                params![job.id, spec, state_to_str(&job.state)],
+            )
+            .map_err(|e| format!("Failed to store job {}: {}", job.id, e))?;
+        Ok(())
+    }
+
+    fn update_job(&self, record: &JobRecord) -> Result<(), String> {
+        let grade = record
+            .grade
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|e| e.to_string())?;
+
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE jobs
+                 SET state = ?2, error = ?3, compiler_output = ?4, finished_at_ms = ?5,
+                     grade = ?6,
+                     binary = CASE WHEN ?5 IS NULL THEN binary END,
+                     checker_binary = CASE WHEN ?5 IS NULL THEN checker_binary END
+                 WHERE id = ?1",
+                params![
+                    record.id,
+                    state_to_str(&record.state),
+                    record.error,
+                    record.compiler_output,
+                    record.finished_at.map(to_unix_ms),
+                    grade,
+                ],
+            )
+            .map_err(|e| format!("Failed to update job {}: {}", record.id, e))?;
+        Ok(())
+    }
+
+    fn insert_binary(&self, job_id: &str, checker: bool, binary: &[u8]) -> Result<(), String> {
+        let sql = if checker {
+            "UPDATE jobs SET checker_binary = ?2 WHERE id = ?1"
+        } else {
+            "UPDATE jobs SET binary = ?2 WHERE id = ?1"
+        };
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(sql, params![job_id, binary])
+            .map_err(|e| format!("Failed to store binary of job {}: {}", job_id, e))?;
+        Ok(())
+    }
+
+    fn update_deliveries(
+        &self,
+        job_id: &str,
+        deliveries: &[WebhookDelivery],
+    ) -> Result<(), String> {
+        let deliveries = serde_json::to_string(deliveries).map_err(|e| e.to_string())?;
+
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE jobs SET webhook_deliveries = ?2 WHERE id = ?1",
+                params![job_id, deliveries],
+            )
+            .map_err(|e| format!("Failed to record deliveries of job {}: {}", job_id, e))?;
+        Ok(())
+    }
+
+    fn insert_batch(
+        &self,
+        job_id: &str,
+        batch_id: &str,
+        results: &[TestCaseResult],
+    ) -> Result<(), String> {
+        let stored: Vec<StoredResult> = results.iter().map(Into::into).collect();
+        let results = serde_json::to_string(&stored).map_err(|e| e.to_string())?;
+
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO batch_results (job_id, batch_id, results)
+                 VALUES (?1, ?2, ?3)",
+                params![job_id, batch_id, results],
+            )
+            .map_err(|e| {
+                format!(
+                    "Failed to store batch {} of job {}: {}",
+                    batch_id, job_id, e
+                )
+            })?;
+        Ok(())
+    }
+
+    fn expire_job(&self, job_id: &str) -> Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "DELETE FROM batch_results WHERE job_id = ?1",
+            params![job_id],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM jobs WHERE id = ?1", params![job_id])
+            .map_err(|e| e.to_string())?;
+
+        // Remember as many expired IDs as the master does in memory
+        tx.execute(
+            "INSERT OR IGNORE INTO expired_jobs (id) VALUES (?1)",
+            params![job_id],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.execute(
+            "DELETE FROM expired_jobs
+             WHERE rowid <= (SELECT MAX(rowid) FROM expired_jobs) - ?1",
+            params![EXPIRED_JOBS_CAPACITY as i64],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit()
+            .map_err(|e| format!("Failed to expire job {}: {}", job_id, e))
+    }
+
+    fn load_expired(&self) -> Result<Vec<String>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT id FROM expired_jobs ORDER BY rowid")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to load expired jobs: {}", e))
+    }
+
+    fn load_jobs(&self, suites: &HashMap<String, Arc<Suite>>) -> Result<Vec<JobContext>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, spec, state, error, compiler_output, binary, finished_at_ms,
+                        checker_binary, grade, webhook_deliveries
+                 FROM jobs ORDER BY rowid",
+            )
+            .map_err(|e| e.to_string())?;
+
+        // A row whose columns cannot be read is skipped like a corrupt one
+        let rows = stmt
+            .query_map([], |row| {
+                let columns = || {
+                    Ok(JobRow {
+                        spec: row.get(1)?,
+                        state: row.get(2)?,
+                        error: row.get(3)?,
+                        compiler_output: row.get(4)?,
+                        binary: row.get(5)?,
+                        finished_at_ms: row.get(6)?,
+                        checker_binary: row.get(7)?,
+                        grade: row.get(8)?,
+                        deliveries: row.get(9)?,
+                    })
+                };
+                Ok((row.get::<_, String>(0)?, columns()))
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut jobs = Vec::new();
+        for row in rows {
+            let (id, columns) = row.map_err(|e| e.to_string())?;
+            let job = columns
+                .map_err(|e: rusqlite::Error| e.to_string())
+                .and_then(|columns| Self::load_job(&conn, suites, id.clone(), columns));
+            match job {
+                Ok(job) => jobs.push(job),
+                Err(e) => warn!(job_id = %id, error = %e, "Skipping job that cannot be loaded"),
+            }
+        }
+
+        Ok(jobs)
+    }
+
+    fn insert_suite(&self, suite: &Suite) -> Result<(), String> {
+        let stored: Vec<StoredTestCase> = suite.test_cases.iter().map(Into::into).collect();
+        let test_cases = serde_json::to_string(&stored).map_err(|e| e.to_string())?;
+
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO suites (name, version, hash, test_cases) VALUES (?1, ?2, ?3, ?4)",
+                params![suite.name, suite.version, suite.hash, test_cases],
+            )
+            .map_err(|e| format!("Failed to store suite {}: {}", suite.reference(), e))?;
+        Ok(())
+    }
+
+    fn load_suites(&self) -> Result<Vec<Suite>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT name, version, hash, test_cases FROM suites ORDER BY name, version")
+            .map_err(|e| e.to_string())?;
+
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut suites = Vec::new();
+        for row in rows {
+            let (name, version, hash, test_cases) = row.map_err(|e| e.to_string())?;
+            let test_cases: Vec<StoredTestCase> = serde_json::from_str(&test_cases)
+                .map_err(|e| format!("Corrupt suite {}@{}: {}", name, version, e))?;
+            suites.push(Suite {
+                name,
+                version,
+                hash,
+                test_cases: Arc::new(test_cases.into_iter().map(Into::into).collect()),
+            });
+        }
+
+        Ok(suites)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: &str) -> JobContext {
+        let test_case = TestCase {
+            id: "1".to_string(),
+            input: vec![0xff, b'\n'],
+            ..Default::default()
+        };
+        JobContext::new(
+            id.to_string(),
+            "cpp".to_string(),
+            "int main() {}".to_string(),
+            vec![],
+            Arc::new(vec![test_case]),
+            1000,
+            128,
+        )
+    }
+
+    #[test]
+    fn corrupt_jobs_are_skipped_on_load() {
+        let store = SqliteJobStore::open(":memory:").unwrap();
+        for id in ["a", "b", "c"] {
+            store.insert_job(&job(id)).unwrap();
+        }
+        let conn = store.conn.lock().unwrap();
+        conn.execute("UPDATE jobs SET spec = '{' WHERE id = 'a'", [])
+            .unwrap();
+        conn.execute("UPDATE jobs SET state = 'lost' WHERE id = 'c'", [])
+            .unwrap();
+        drop(conn);
+
+        let jobs = store.load_jobs(&HashMap::new()).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, "b");
+        assert_eq!(jobs[0].test_cases[0].input, vec![0xff, b'\n']);
+    }
+}
@@ -0,0 +1,205 @@
+//! Master Node - Test Suites
+//!
+//! Named, versioned test suites are uploaded once and referenced by
+//! submissions as `name@version` (or `name` for the latest version). Workers
+//! fetch a suite by content hash and cache it, so batches of jobs that use a
+//! suite only carry test ids.
+
+use crate::state::AppState;
+use common::scheduler::{SuiteData, TestCase};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Longest accepted suite name
+const MAX_NAME_LEN: usize = 128;
+/// Largest total size of a suite's test cases
+pub const MAX_SUITE_BYTES: usize = 64 * 1024 * 1024;
+/// Largest upload request: inputs may be base64-encoded, a third larger than
+/// the bytes they carry
+pub const MAX_UPLOAD_BYTES: usize = MAX_SUITE_BYTES / 3 * 4 + 1024 * 1024;
+
+/// One version of a test suite
+pub struct Suite {
+    pub name: String,
+    /// Starts at 1 and grows with every upload that changes the test cases
+    pub version: u32,
+    /// Content hash of the test cases (see `SuiteData`)
+    pub hash: String,
+    pub test_cases: Arc<Vec<TestCase>>,
+}
+
+impl Suite {
+    /// `name@version`, as referenced by submissions
+    pub fn reference(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+}
+
+/// Content hash of a suite's test cases
+pub fn content_hash(test_cases: &[TestCase]) -> String {
+    let data = SuiteData {
+        hash: String::new(),
+        test_cases: test_cases.to_vec(),
+    };
+    hex::encode(Sha256::digest(data.encode_to_vec()))
+}
+
+/// Check an upload: a name made of letters, digits, `-`, `_` and `.`, and
+/// at most `MAX_SUITE_BYTES` of test cases with unique ids
+pub fn validate(name: &str, test_cases: &[TestCase]) -> Result<(), String> {
+    let valid_name = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid_name {
+        return Err(format!("Invalid suite name: {}", name));
+    }
+
+    let size: usize = test_cases
+        .iter()
+        .map(|tc| tc.id.len() + tc.input.len() + tc.expected_output.len())
+        .sum();
+    if size > MAX_SUITE_BYTES {
+        return Err(format!("Suite is larger than {} bytes", MAX_SUITE_BYTES));
+    }
+
+    unique_ids(test_cases)
+}
+
+/// Check that no two test cases share an id: results are matched to tests
+/// (and subtasks) by id
+pub fn unique_ids(test_cases: &[TestCase]) -> Result<(), String> {
+    let mut ids = HashSet::new();
+    if let Some(duplicate) = test_cases.iter().find(|tc| !ids.insert(tc.id.as_str())) {
+        return Err(format!("Duplicate test case id: {}", duplicate.id));
+    }
+    Ok(())
+}
+
+/// Register a new version of a suite, unless its test cases are the same as
+/// the latest version's. Returns the suite and whether a version was created.
+///
+/// The suite is stored off the async threads, without holding its entry in
+/// `state.suites`, so lookups by running submissions are never blocked on it.
+pub async fn register(
+    state: &AppState,
+    name: &str,
+    test_cases: Vec<TestCase>,
+) -> Result<(Arc<Suite>, bool), String> {
+    let hash = content_hash(&test_cases);
+    let _upload = state.suite_uploads.lock().await;
+
+    let version = match state.suites.get(name) {
+        Some(versions) => {
+            if let Some(latest) = versions.last().filter(|latest| latest.hash == hash) {
+                return Ok((latest.clone(), false));
+            }
+            versions.len() as u32 + 1
+        }
+        None => 1,
+    };
+
+    let suite = Arc::new(Suite {
+        name: name.to_string(),
+        version,
+        hash,
+        test_cases: Arc::new(test_cases),
+    });
+    let store = state.store.clone();
+    let stored = suite.clone();
+    tokio::task::spawn_blocking(move || store.insert_suite(&stored))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    state
+        .suites
+        .entry(name.to_string())
+        .or_default()
+        .push(suite.clone());
+    Ok((suite, true))
+}
+
+/// Look up a suite by `name@version`, or the latest version by `name`
+pub fn resolve(state: &AppState, reference: &str) -> Option<Arc<Suite>> {
+    let (name, version) = match reference.split_once('@') {
+        Some((name, version)) => (name, Some(version.parse::<u32>().ok()?)),
+        None => (reference, None),
+    };
+
+    let versions = state.suites.get(name)?;
+    match version {
+        Some(version) => versions.iter().find(|s| s.version == version).cloned(),
+        None => versions.last().cloned(),
+    }
+}
+
+/// Look up a suite by content hash
+pub fn find_by_hash(state: &AppState, hash: &str) -> Option<Arc<Suite>> {
+    state
+        .suites
+        .iter()
+        .find_map(|entry| entry.value().iter().find(|s| s.hash == hash).cloned())
+}
+
+/// Load every stored suite into memory; returns how many were loaded
+pub fn load(state: &AppState) -> Result<usize, String> {
+    let suites = state.store.load_suites()?;
+    let count = suites.len();
+
+    for suite in suites {
+        state
+            .suites
+            .entry(suite.name.clone())
+            .or_default()
+            .push(Arc::new(suite));
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::SqliteJobStore;
+
+    fn test_case(id: &str, expected_output: &str) -> TestCase {
+        TestCase {
+            id: id.to_string(),
+            expected_output: expected_output.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn identical_uploads_reuse_the_latest_version() {
+        let state = AppState::new(Arc::new(SqliteJobStore::open(":memory:").unwrap()));
+
+        let (first, created) = register(&state, "sum", vec![test_case("1", "3")])
+            .await
+            .unwrap();
+        assert!(created);
+        assert_eq!(first.version, 1);
+
+        let (again, created) = register(&state, "sum", vec![test_case("1", "3")])
+            .await
+            .unwrap();
+        assert!(!created);
+        assert_eq!(again.version, 1);
+
+        let (changed, created) = register(&state, "sum", vec![test_case("1", "4")])
+            .await
+            .unwrap();
+        assert!(created);
+        assert_eq!(changed.version, 2);
+        assert_eq!(state.store.load_suites().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn oversized_suites_are_rejected() {
+        let half = "x".repeat(MAX_SUITE_BYTES / 2);
+        assert!(validate("big", &[test_case("1", &half)]).is_ok());
+        assert!(validate("big", &[test_case("1", &half), test_case("2", &half)]).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! Master Node - Completion Webhooks
+//!
+//! POSTs a job's final status to the `callback_url` given at submission once
+//! the job completes. Payloads are signed with HMAC-SHA256 when a secret was
+//! given, and failed deliveries are retried with exponential backoff. Every
+//! attempt is recorded in the store, so a delivery cut short by a restart is
+//! resumed.
+//!
+//! Callbacks may only reach public addresses, so a submission cannot make the
+//! master call into its own network (loopback, private ranges, cloud metadata
+//! endpoints). Hosts listed in `WEBHOOK_ALLOWED_HOSTS` (comma-separated) are
+//! exempt. The check runs at submission and again whenever the host is
+//! resolved for a delivery; redirects are not followed.
+
+use crate::http::StatusResponse;
+use crate::state::{AppState, JobContext, JobEvent, JobState, WebhookDelivery};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Header carrying `sha256=<hex HMAC of the body>`
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to report a job's completion
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub url: String,
+    /// Key for the payload signature; unsigned if absent
+    pub secret: Option<String>,
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()
+            .expect("Failed to build webhook HTTP client")
+    })
+}
+
+/// Hosts exempt from the public address check, from `WEBHOOK_ALLOWED_HOSTS`
+fn allowed_hosts() -> &'static HashSet<String> {
+    static HOSTS: OnceLock<HashSet<String>> = OnceLock::new();
+    HOSTS.get_or_init(|| {
+        std::env::var("WEBHOOK_ALLOWED_HOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect()
+    })
+}
+
+/// Whether `ip` is reachable on the public internet, as opposed to loopback,
+/// private, link-local, shared, reserved or multicast ranges
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Resolve `host` and check that every address it has is public
+async fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .collect();
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(format!(
+            "{} resolves to non-public address {}",
+            host,
+            addr.ip()
+        ));
+    }
+    Ok(addrs)
+}
+
+/// DNS resolver of the webhook client: refuses hosts with non-public
+/// addresses, so a host cannot be pointed elsewhere after it was validated
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs = if allowed_hosts().contains(host) {
+                tokio::net::lookup_host((host, 0)).await?.collect()
+            } else {
+                resolve_public(host, 0).await?
+            };
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Check that a callback URL is an absolute http(s) URL of a public host, or
+/// of one of `WEBHOOK_ALLOWED_HOSTS`
+pub async fn validate_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid callback_url: {}", e))?;
+    match parsed.scheme() {
+        "http" | "https" => {}
+        scheme => return Err(format!("Unsupported callback_url scheme: {}", scheme)),
+    }
+
+    let Some(host) = parsed.host_str() else {
+        return Err("callback_url has no host".to_string());
+    };
+    if allowed_hosts().contains(host) {
+        return Ok(());
+    }
+
+    // IPv6 hosts keep their brackets in URLs
+    let ip = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .ok();
+    match ip {
+        Some(ip) if !is_public(ip) => Err(format!(
+            "callback_url must not point at a non-public address: {}",
+            ip
+        )),
+        Some(_) => Ok(()),
+        None => resolve_public(host, parsed.port_or_known_default().unwrap_or(80))
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Invalid callback_url: {}", e)),
+    }
+}
+
+/// `sha256=<hex>` signature of `body` under `secret`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether a completed job's webhook is still to be delivered: no attempt
+/// has succeeded and attempts are left
+pub fn pending(job: &JobContext) -> bool {
+    matches!(job.state, JobState::Completed)
+        && job.webhook.is_some()
+        && job.webhook_deliveries.len() < MAX_DELIVERY_ATTEMPTS as usize
+        && job.webhook_deliveries.iter().all(|d| d.error.is_some())
+}
+
+/// Wait for a job to finish and deliver its webhook if it completed
+///
+/// `events` must be subscribed before the job can finish, so the final event
+/// is not missed.
+pub async fn watch_job(state: AppState, job_id: String, mut events: broadcast::Receiver<JobEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(JobEvent::Finished {
+                state: JobState::Completed,
+                ..
+            }) => break,
+            Ok(JobEvent::Finished { .. }) => return,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+
+    deliver(&state, &job_id).await;
+}
+
+/// POST the job's final status to its webhook, retrying with backoff; the
+/// attempts already recorded for the job count against the limit
+pub async fn deliver(state: &AppState, job_id: &str) {
+    let (webhook, body, first_attempt) = {
+        let Some(job) = state.jobs.get(job_id) else {
+            return;
+        };
+        let Some(webhook) = job.webhook.clone() else {
+            return;
+        };
+        let body = match serde_json::to_vec(&StatusResponse::for_job(state, &job)) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!(job_id = %job_id, error = %e, "Failed to serialise webhook payload");
+                return;
+            }
+        };
+        (webhook, body, job.webhook_deliveries.len() as u32 + 1)
+    };
+    let signature = webhook.secret.as_deref().map(|secret| sign(secret, &body));
+
+    for attempt in first_attempt..=MAX_DELIVERY_ATTEMPTS {
+        let mut request = client()
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Job-Id", job_id)
+            .header("X-Delivery-Attempt", attempt.to_string())
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+
+        let (status_code, error) = match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16()), None)
+            }
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                Some(format!("Unexpected status {}", response.status())),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let delivered = error.is_none();
+
+        if let Some(mut job) = state.jobs.get_mut(job_id) {
+            job.webhook_deliveries.push(WebhookDelivery {
+                attempt,
+                at_unix_ms: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0),
+                status_code,
+                error: error.clone(),
+            });
+            state.persist_deliveries(&job);
+        }
+
+        if delivered {
+            info!(job_id = %job_id, attempt = attempt, "Webhook delivered");
+            return;
+        }
+
+        warn!(
+            job_id = %job_id,
+            attempt = attempt,
+            error = %error.unwrap_or_default(),
+            "Webhook delivery failed"
+        );
+
+        if attempt < MAX_DELIVERY_ATTEMPTS {
+            tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+        }
+    }
+
+    warn!(job_id = %job_id, "Giving up on webhook delivery");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in ["93.184.216.34", "8.8.8.8", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn callback_urls_must_be_public() {
+        assert!(validate_url("https://93.184.216.34/hook").await.is_ok());
+        assert!(validate_url("http://[2606:4700::1111]:8080/").await.is_ok());
+
+        for url in [
+            "http://127.0.0.1:8080/status",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/",
+            "http://[::ffff:10.0.0.1]/",
+            "ftp://93.184.216.34/",
+            "not a url",
+        ] {
+            assert!(validate_url(url).await.is_err(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Worker Node - Output Comparison
+//!
+//! Decides whether a program's stdout matches a test's expected output
+//! according to the job's (or test's) comparator, and turns what a checker
+//! program reported into a verdict.
+
+use common::scheduler::{comparator::Mode, Comparator};
+
+/// Longest checker message kept in a test result, in characters
+const MAX_CHECKER_MESSAGE_CHARS: usize = 1024;
+
+/// A checker's judgement of one test
+pub struct Verdict {
+    pub status: &'static str,
+    pub score: f64,
+    pub message: String,
+}
+
+impl Verdict {
+    pub fn passed() -> Self {
+        Self {
+            status: "PASSED",
+            score: 1.0,
+            message: String::new(),
+        }
+    }
+
+    pub fn failed(status: &'static str) -> Self {
+        Self {
+            status,
+            score: 0.0,
+            message: String::new(),
+        }
+    }
+}
+
+/// Check `actual` against `expected`; `None` means the default trimmed equality
+///
+/// `Exact` compares the raw bytes, so output that is not valid UTF-8 never
+/// matches; the other modes compare the output decoded lossily
+pub fn outputs_match(comparator: Option<&Comparator>, actual: &[u8], expected: &str) -> bool {
+    let text = String::from_utf8_lossy(actual);
+    let Some(comparator) = comparator else {
+        return text.trim() == expected.trim();
+    };
+
+    match comparator.mode() {
+        Mode::Trim => text.trim() == expected.trim(),
+        Mode::Exact => actual == expected.as_bytes(),
+        Mode::Lines => lines(&text).eq(lines(expected)),
+        Mode::Tokens => text.split_whitespace().eq(expected.split_whitespace()),
+        Mode::CaseInsensitive => normalize_newlines(text.trim())
+            .to_lowercase()
+            .eq(&normalize_newlines(expected.trim()).to_lowercase()),
+        Mode::Float => {
+            let mut actual_tokens = text.split_whitespace();
+            let mut expected_tokens = expected.split_whitespace();
+            loop {
+                match (actual_tokens.next(), expected_tokens.next()) {
+                    (None, None) => return true,
+                    (Some(a), Some(e)) if tokens_match(comparator, a, e) => continue,
+                    _ => return false,
+                }
+            }
+        }
+    }
+}
+
+/// Lines with surrounding whitespace (including `\r`) removed, without
+/// trailing blank lines
+fn lines(output: &str) -> impl Iterator<Item = &str> {
+    let lines: Vec<&str> = output.lines().map(str::trim).collect();
+    let len = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(0, |i| i + 1);
+    lines.into_iter().take(len)
+}
+
+fn normalize_newlines(output: &str) -> String {
+    output.replace("\r\n", "\n")
+}
+
+/// Numeric tokens match within the comparator's epsilons; others must be equal
+fn tokens_match(comparator: &Comparator, actual: &str, expected: &str) -> bool {
+    if actual == expected {
+        return true;
+    }
+
+    match (actual.parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(a), Ok(e)) if a.is_finite() && e.is_finite() => {
+            let diff = (a - e).abs();
+            diff <= comparator.abs_epsilon || diff <= comparator.rel_epsilon * e.abs()
+        }
+        _ => false,
+    }
+}
+
+/// Interpret a checker's exit code and output (see `Checker` in the proto)
+pub fn checker_verdict(exit_code: i64, output: &str) -> Verdict {
+    let output = output.trim();
+    let verdict = |status, score, message: &str| Verdict {
+        status,
+        score,
+        message: message.chars().take(MAX_CHECKER_MESSAGE_CHARS).collect(),
+    };
+
+    match exit_code {
+        0 => verdict("PASSED", 1.0, output),
+        1 | 2 => verdict("FAILED", 0.0, output),
+        7 => {
+            let (score, message) = output
+                .split_once(char::is_whitespace)
+                .unwrap_or((output, ""));
+            match score.parse::<f64>() {
+                Ok(score) if (0.0..=1.0).contains(&score) => {
+                    verdict("PARTIAL", score, message.trim_start())
+                }
+                _ => verdict(
+                    "CHECKER_ERROR",
+                    0.0,
+                    &format!("Invalid checker score: {}", score),
+                ),
+            }
+        }
+        code => verdict(
+            "CHECKER_ERROR",
+            0.0,
+            &format!("Checker exited with code {}: {}", code, output),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comparator(mode: Mode) -> Comparator {
+        Comparator {
+            mode: mode as i32,
+            ..Default::default()
+        }
+    }
+
+    fn float(abs_epsilon: f64, rel_epsilon: f64) -> Comparator {
+        Comparator {
+            mode: Mode::Float as i32,
+            abs_epsilon,
+            rel_epsilon,
+        }
+    }
+
+    fn matches(comparator: &Comparator, actual: &str, expected: &str) -> bool {
+        outputs_match(Some(comparator), actual.as_bytes(), expected)
+    }
+
+    #[test]
+    fn default_trims_surrounding_whitespace() {
+        assert!(outputs_match(None, b"  42\n\n", "42"));
+        assert!(outputs_match(None, b"42\r\n", "42\n"));
+        assert!(!outputs_match(None, b"4 2", "42"));
+        assert!(matches(&comparator(Mode::Trim), "\t1 2\n", "1 2"));
+    }
+
+    #[test]
+    fn exact_compares_bytes() {
+        let exact = comparator(Mode::Exact);
+        assert!(matches(&exact, "1 2\n", "1 2\n"));
+        assert!(!matches(&exact, "1 2", "1 2\n"));
+        assert!(!matches(&exact, "1 2\r\n", "1 2\n"));
+        assert!(!matches(&exact, "1 2 \n", "1 2\n"));
+
+        // Lossy decoding would turn both into U+FFFD
+        assert!(!outputs_match(Some(&exact), b"\xff", "\u{fffd}"));
+        assert!(outputs_match(Some(&exact), "é".as_bytes(), "é"));
+    }
+
+    #[test]
+    fn lines_ignore_line_endings_and_trailing_blank_lines() {
+        let lines = comparator(Mode::Lines);
+        assert!(matches(&lines, "a b\r\nc  \r\n\r\n", "a b\nc"));
+        assert!(matches(&lines, "  a\nb\n", "a\nb\n\n\n"));
+        assert!(!matches(&lines, "a\n\nb", "a\nb"));
+        assert!(!matches(&lines, "a  b", "a b"));
+    }
+
+    #[test]
+    fn tokens_ignore_all_whitespace() {
+        let tokens = comparator(Mode::Tokens);
+        assert!(matches(&tokens, "1\r\n2   3\n", "1 2 3"));
+        assert!(!matches(&tokens, "1 2", "1 2 3"));
+        assert!(!matches(&tokens, "12 3", "1 2 3"));
+    }
+
+    #[test]
+    fn case_insensitive_trims_and_normalizes_newlines() {
+        let case = comparator(Mode::CaseInsensitive);
+        assert!(matches(&case, " YES\r\nNo \n", "yes\nno"));
+        assert!(!matches(&case, "yes no", "yes\nno"));
+    }
+
+    #[test]
+    fn float_absolute_epsilon() {
+        let abs = float(1e-6, 0.0);
+        assert!(matches(&abs, "0.3333334\n", "0.3333333"));
+        assert!(matches(&abs, "1.0000005 word", "1 word"));
+        assert!(!matches(&abs, "0.334", "0.333"));
+        assert!(!matches(&abs, "1.0 words", "1.0 word"));
+        assert!(!matches(&abs, "1.0", "1.0 2.0"));
+    }
+
+    #[test]
+    fn float_relative_epsilon() {
+        let rel = float(0.0, 1e-6);
+        assert!(matches(&rel, "1000000.5", "1000000"));
+        assert!(!matches(&rel, "1.5", "1"));
+        assert!(!matches(&rel, "0.0000001", "0"));
+    }
+
+    #[test]
+    fn float_non_finite_must_match_textually() {
+        let abs = float(1e9, 1e9);
+        assert!(matches(&abs, "nan inf", "nan inf"));
+        assert!(!matches(&abs, "NaN", "nan"));
+        assert!(!matches(&abs, "nan", "0"));
+        assert!(!matches(&abs, "inf", "1e400"));
+        assert!(!matches(&abs, "inf", "1e308"));
+        assert!(!matches(&abs, "-inf", "inf"));
+    }
+
+    #[test]
+    fn checker_exit_codes() {
+        assert_eq!(checker_verdict(0, "ok\n").status, "PASSED");
+        assert_eq!(checker_verdict(1, "wrong").status, "FAILED");
+
+        let partial = checker_verdict(7, "0.25 close enough");
+        assert_eq!(partial.status, "PARTIAL");
+        assert_eq!(partial.score, 0.25);
+        assert_eq!(partial.message, "close enough");
+
+        assert_eq!(checker_verdict(7, "1.5").status, "CHECKER_ERROR");
+        assert_eq!(checker_verdict(3, "").status, "CHECKER_ERROR");
+    }
+}
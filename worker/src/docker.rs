@@ -3,137 +3,356 @@
 //! Uses bollard to interact with Docker for sandboxed code execution.
 
 use bollard::container::{
-    Config, CreateContainerOptions, RemoveContainerOptions, StartContainerOptions,
-    UploadToContainerOptions,
+    ListContainersOptions, LogOutput, RestartContainerOptions, UploadToContainerOptions,
 };
 use bollard::exec::{CreateExecOptions, StartExecResults};
 use bollard::Docker;
+use crate::compare::{checker_verdict, outputs_match, Verdict};
+use crate::pool::{ContainerPool, PoolConfig, PoolKind};
+use crate::sandbox::{INSTALL_USER, PROGRAM_DIR, SCRATCH_DIR};
+use common::{build_command_argv, canonical_language, LANGUAGES, MAX_PROJECT_PATH_LEN};
 use common::scheduler::{
-    BatchExecutionResult, CompileResult, ResourceMetrics, TestCase, TestCaseResult,
+    execute_batch_task::{self, Isolation},
+    BatchExecutionResult, Checker, CompileResult, CompileTask, ExecuteBatchTask, PoolStats,
+    ResourceMetrics, TestCase, TestCaseResult,
 };
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, OnceCell};
 use tokio::time::timeout;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// How long a checker or interactor may take per test if the task sets no limit
+const DEFAULT_CHECKER_TIMEOUT: Duration = Duration::from_secs(10);
+/// Memory limit of checker and interactor containers
+const CHECKER_MEMORY_MB: u32 = 256;
+/// How long a project build may take
+const PROJECT_BUILD_TIMEOUT: Duration = Duration::from_secs(240);
+/// How long unpacking files into a run container may take
+const UNPACK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest compiled artifact (binary or Java class bundle) a build may produce
+const MAX_ARTIFACT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Source of the runner that measures each test (see the file for its protocol)
+const RUNNER_SOURCE: &str = include_str!("runner.c");
+/// Where the runner is installed in run containers (in `PROGRAM_DIR`)
+const RUNNER_PATH: &str = "/box/.runner";
+/// Marks the runner's report at the end of stderr
+const USAGE_MARKER: &str = "\n__RUSAGE__ ";
+/// With CPU time measured, wall-clock time only stops programs that block
+/// (e.g. sleep or wait for input): it may be this many times the limit
+const WALL_TIME_FACTOR: u32 = 3;
+/// How long past the wall-clock limit the runner gets to kill a run and
+/// report before the worker gives up on it and resets the container
+const RUNNER_GRACE: Duration = Duration::from_secs(2);
+/// Exit code of a process killed by SIGKILL (the hard CPU limit, the OOM
+/// killer, the runner's wall-clock limit or the program itself)
+const SIGKILL_EXIT: i64 = 128 + 9;
+/// Exit code of a process killed by SIGXCPU (the soft CPU limit)
+const SIGXCPU_EXIT: i64 = 128 + 24;
+
+/// Output of an exec started with its streams attached
+type ExecOutput = Pin<Box<dyn Stream<Item = Result<LogOutput, bollard::errors::Error>> + Send>>;
+/// Stdin of an exec started with its streams attached
+type ExecInput = Pin<Box<dyn AsyncWrite + Send>>;
+/// One command of a build, run without a shell
+type Argv = Vec<String>;
+
+/// Why a command run in a container did not finish
+#[derive(Debug)]
+enum ExecError {
+    /// It ran past its time limit and may still be running
+    Timeout,
+    /// Docker could not start it or collect its output
+    Failed(String),
+}
+
+impl std::fmt::Display for ExecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecError::Timeout => write!(f, "Execution timeout"),
+            ExecError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<ExecError> for String {
+    fn from(e: ExecError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Resources used by one run of a submission, as reported by the runner
+struct Usage {
+    exit_code: i64,
+    cpu_ms: u64,
+    peak_bytes: u64,
+    /// Killed by the runner for exceeding the wall-clock limit
+    timed_out: bool,
+    /// The container's memory cgroup OOM-killed a process during the run
+    oom_killed: bool,
+}
+
+impl Usage {
+    /// Used more CPU time than allowed, or was killed for it
+    fn over_time(&self, limit_ms: u32) -> bool {
+        let limit_ms = limit_ms as u64;
+        self.timed_out
+            || self.cpu_ms > limit_ms
+            || self.exit_code == SIGXCPU_EXIT
+            || (self.exit_code == SIGKILL_EXIT && self.cpu_ms >= limit_ms)
+    }
+
+    /// Used more memory than allowed, or hit the limit and was OOM-killed;
+    /// a SIGKILL by itself says nothing about memory
+    fn out_of_memory(&self, limit_mb: u32) -> bool {
+        self.peak_bytes > limit_mb as u64 * 1024 * 1024 || self.oom_killed
+    }
+}
+
+/// How the tests of a batch are run and limited
+struct RunSpec<'a> {
+    /// Runs the submission (under the runner, if installed)
+    cmd: &'a str,
+    /// Whether `cmd` runs under the runner
+    measured: bool,
+    time_limit_ms: u32,
+    memory_limit_mb: u32,
+    /// Wall-clock limit per test, enforced by the runner if measured
+    wall_limit: Duration,
+    /// Installed again whenever a container is reset
+    program: &'a Program<'a>,
+}
+
+/// One run of the submission, before its output is judged
+struct RunOutcome {
+    time_ms: u64,
+    peak_bytes: u64,
+    /// TLE, MLE or RE if the run failed; its output is then not judged
+    failure: Option<&'static str>,
+}
+
+impl RunSpec<'_> {
+    /// How long to wait for a run before giving up on it
+    fn deadline(&self) -> Duration {
+        if self.measured {
+            self.wall_limit + RUNNER_GRACE
+        } else {
+            self.wall_limit
+        }
+    }
+
+    /// Measure a run that finished before its deadline, taking the
+    /// runner's report off its stderr. Unmeasured runs report wall-clock time.
+    fn outcome(&self, exit_code: i64, stderr: &mut String, elapsed: Duration) -> RunOutcome {
+        let usage = if self.measured {
+            take_usage(stderr)
+        } else {
+            None
+        };
+
+        match usage {
+            Some(usage) => RunOutcome {
+                time_ms: usage.cpu_ms,
+                peak_bytes: usage.peak_bytes,
+                failure: if usage.over_time(self.time_limit_ms) {
+                    Some("TLE")
+                } else if usage.out_of_memory(self.memory_limit_mb) {
+                    Some("MLE")
+                } else if usage.exit_code != 0 {
+                    Some("RE")
+                } else {
+                    None
+                },
+            },
+            None => RunOutcome {
+                time_ms: elapsed.as_millis() as u64,
+                peak_bytes: 0,
+                failure: match exit_code {
+                    0 => None,
+                    _ => Some("RE"),
+                },
+            },
+        }
+    }
+}
+
+/// What is installed in each run container of a batch
+struct Program<'a> {
+    /// File name and source, for interpreted languages
+    source: Option<(&'static str, &'a str)>,
+    /// Compiled artifact (a class bundle for Java)
+    binary: Option<&'a [u8]>,
+    is_java: bool,
+    runner: Option<&'a [u8]>,
+}
+
+impl<'a> Program<'a> {
+    /// A checker or interactor: always a compiled artifact, never measured
+    fn checker(checker: &'a Checker) -> Self {
+        Self {
+            source: None,
+            binary: Some(&checker.binary),
+            is_java: canonical_language(&checker.language) == Some("java"),
+            runner: None,
+        }
+    }
+}
 
 /// Docker executor for sandboxed code execution
 pub struct DockerExecutor {
     docker: Docker,
+    /// Warm build and run containers
+    pool: Arc<ContainerPool>,
+    /// Runner binary, built on first use; `None` if it could not be built
+    runner: OnceCell<Option<Vec<u8>>>,
 }
 
 impl DockerExecutor {
     pub fn new() -> Result<Self, bollard::errors::Error> {
         let docker = Docker::connect_with_local_defaults()?;
-        Ok(Self { docker })
+        Ok(Self {
+            pool: ContainerPool::new(docker.clone(), PoolConfig::from_env()),
+            docker,
+            runner: OnceCell::new(),
+        })
+    }
+
+    /// Remove pooled containers a previous run of the worker left behind;
+    /// call before any task starts
+    pub async fn remove_orphaned_containers(&self) {
+        let removed = self.pool.remove_orphans().await;
+        if removed > 0 {
+            info!(removed, "Removed orphaned pool containers");
+        }
+    }
+
+    /// Statistics of the warm container pools, for heartbeats
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        self.pool.stats()
+    }
+
+    /// The runner that measures tests, built once with the C compile image.
+    /// Without it tests report wall-clock time and no memory usage.
+    async fn runner(&self) -> Option<&[u8]> {
+        self.runner
+            .get_or_init(|| async {
+                let task = CompileTask {
+                    job_id: format!("runner-{}", Uuid::new_v4()),
+                    language: "c".to_string(),
+                    source_code: RUNNER_SOURCE.to_string(),
+                    flags: vec!["-O2".to_string()],
+                    ..Default::default()
+                };
+                let result = self.compile(&task).await;
+                if result.success {
+                    info!(bytes = result.binary_payload.len(), "Built test runner");
+                    Some(result.binary_payload)
+                } else {
+                    warn!(
+                        output = %result.compiler_output,
+                        "Failed to build test runner; tests will be timed by wall clock"
+                    );
+                    None
+                }
+            })
+            .await
+            .as_deref()
     }
 
     /// Compile source code and return the binary
-    pub async fn compile(
-        &self,
-        job_id: &str,
-        language: &str,
-        source_code: &str,
-        flags: &[String],
-    ) -> CompileResult {
+    pub async fn compile(&self, task: &CompileTask) -> CompileResult {
         let start = Instant::now();
+        let job_id = task.job_id.as_str();
+        let language = task.language.as_str();
+        let source_code = task.source_code.as_str();
+        let flags = &task.flags;
+        let checker = task.checker;
+
+        let unsupported = || CompileResult {
+            job_id: job_id.to_string(),
+            checker,
+            success: false,
+            compiler_output: format!("Unsupported compiled language: {}. Interpreted languages (python, javascript, ruby) don't need compilation.", language),
+            binary_payload: vec![],
+            duration_ms: 0,
+        };
+        let failed = |compiler_output: String| CompileResult {
+            job_id: job_id.to_string(),
+            checker,
+            success: false,
+            compiler_output,
+            binary_payload: vec![],
+            duration_ms: start.elapsed().as_millis() as i32,
+        };
 
         // Select image and compile command based on language
         // For Java, we compile to bytecode and package it
-        let (image, src_file, compile_cmd) = match language.to_lowercase().as_str() {
-            "cpp" | "c++" => (
-                "gcc:latest",
-                "main.cpp",
-                format!("g++ -static {} -o /tmp/main /tmp/main.cpp", flags.join(" ")),
-            ),
-            "c" => (
-                "gcc:latest",
-                "main.c",
-                format!("gcc -static {} -o /tmp/main /tmp/main.c", flags.join(" ")),
-            ),
-            "rust" => (
-                "rust:latest",
-                "main.rs",
-                format!("rustc {} -o /tmp/main /tmp/main.rs", flags.join(" ")),
-            ),
-            "go" | "golang" => (
-                "golang:latest",
-                "main.go",
-                "go build -o /tmp/main /tmp/main.go".to_string(),
-            ),
-            "java" => (
-                "eclipse-temurin:25",
-                "Main.java",
-                // Compile to /tmp/classes, then create tarball with classes and wrapper script
-                "mkdir -p /tmp/classes && javac /tmp/Main.java -d /tmp/classes && \
-                 cd /tmp && tar -cf /tmp/java_bundle.tar -C /tmp/classes . && \
-                 echo '#!/bin/sh\njava -cp /tmp/classes Main' > /tmp/main && chmod +x /tmp/main && \
-                 tar -rf /tmp/java_bundle.tar -C /tmp main".to_string(),
-            ),
-            _ => {
-                return CompileResult {
-                    job_id: job_id.to_string(),
-                    success: false,
-                    compiler_output: format!("Unsupported compiled language: {}. Interpreted languages (python, javascript, ruby) don't need compilation.", language),
-                    binary_payload: vec![],
-                    duration_ms: 0,
-                };
+        let Some(image) = compile_image(language) else {
+            return unsupported();
+        };
+        // Projects upload their whole tree to /tmp/src and build it there instead
+        let (files, steps, working_dir) = if task.files.is_empty() {
+            let Some((src_file, steps)) = source_build(language, flags) else {
+                return unsupported();
+            };
+            let files = vec![(src_file.to_string(), source_code.as_bytes())];
+            (files, steps, "/tmp")
+        } else {
+            let allowed = canonical_language(language)
+                .and_then(|language| build_command_argv(language, &task.build_command));
+            if !task.build_command.is_empty() && allowed.is_none() {
+                return failed(format!("Build command not allowed: {}", task.build_command));
+            }
+            let Some(steps) = project_build(task) else {
+                return unsupported();
+            };
+            if let Some(f) = task
+                .files
+                .iter()
+                .find(|f| f.path.len() > MAX_PROJECT_PATH_LEN)
+            {
+                return failed(format!("File path is too long: {}", f.path));
             }
+            let files = task
+                .files
+                .iter()
+                .map(|f| (format!("src/{}", f.path), f.content.as_slice()))
+                .collect();
+            (files, steps, "/tmp/src")
         };
-
-        // Create container
-        let container_name = format!("compile_{}", job_id.replace('-', "_"));
-        let config = Config {
-            image: Some(image.to_string()),
-            cmd: Some(vec!["sleep".to_string(), "300".to_string()]),
-            host_config: Some(bollard::service::HostConfig {
-                memory: Some(512 * 1024 * 1024), // 512MB
-                nano_cpus: Some(2_000_000_000),  // 2 CPUs
-                network_mode: Some("none".to_string()),
-                ..Default::default()
-            }),
-            ..Default::default()
+        let files: Vec<(&str, &[u8])> = files.iter().map(|(p, c)| (p.as_str(), *c)).collect();
+        let sources = match create_tar_archive_files(&files) {
+            Ok(sources) => sources,
+            Err(e) => return failed(e),
         };
 
-        match self
-            .docker
-            .create_container(
-                Some(CreateContainerOptions {
-                    name: container_name.clone(),
-                    platform: None,
-                }),
-                config,
-            )
-            .await
-        {
-            Ok(_) => {}
-            Err(e) => {
-                return CompileResult {
-                    job_id: job_id.to_string(),
-                    success: false,
-                    compiler_output: format!("Failed to create container: {}", e),
-                    binary_payload: vec![],
-                    duration_ms: start.elapsed().as_millis() as i32,
-                };
-            }
+        // Take a build container
+        let mut container_name = format!("compile_{}", job_id.replace('-', "_"));
+        if checker {
+            container_name.push_str("_checker");
         }
-
-        // Start container
         if let Err(e) = self
-            .docker
-            .start_container(&container_name, None::<StartContainerOptions<String>>)
+            .pool
+            .checkout(PoolKind::Build, image, &container_name, 0)
             .await
         {
-            let _ = self.cleanup_container(&container_name).await;
             return CompileResult {
                 job_id: job_id.to_string(),
+                checker,
                 success: false,
-                compiler_output: format!("Failed to start container: {}", e),
+                compiler_output: e,
                 binary_payload: vec![],
                 duration_ms: start.elapsed().as_millis() as i32,
             };
         }
 
         // Upload source code
-        let tar_data = create_tar_archive(src_file, source_code.as_bytes());
         if let Err(e) = self
             .docker
             .upload_to_container(
@@ -142,13 +361,14 @@ impl DockerExecutor {
                     path: "/tmp",
                     ..Default::default()
                 }),
-                tar_data.into(),
+                sources.into(),
             )
             .await
         {
-            let _ = self.cleanup_container(&container_name).await;
+            self.pool.release(&container_name).await;
             return CompileResult {
                 job_id: job_id.to_string(),
+                checker,
                 success: false,
                 compiler_output: format!("Failed to upload source: {}", e),
                 binary_payload: vec![],
@@ -156,36 +376,45 @@ impl DockerExecutor {
             };
         }
 
-        // Execute compile command
+        // Run the build, without a shell (projects get longer to build)
+        let build_timeout = if task.files.is_empty() {
+            Duration::from_secs(60)
+        } else {
+            PROJECT_BUILD_TIMEOUT
+        };
         let exec_result = self
-            .exec_in_container(&container_name, &compile_cmd, Duration::from_secs(60))
+            .run_build(&container_name, &steps, working_dir, build_timeout)
             .await;
 
-        let (success, compiler_output) = match exec_result {
+        let (mut success, mut compiler_output) = match exec_result {
             Ok((exit_code, output)) => (exit_code == 0, output),
-            Err(e) => (false, e),
+            Err(e) => (false, e.to_string()),
         };
 
         // Download binary if successful
-        // For Java, download the bundle tarball instead of just the wrapper
-        let binary_payload = if success {
-            let download_path = if language.to_lowercase() == "java" {
+        // For Java, download the bundle of classes instead of a binary
+        let mut binary_payload = vec![];
+        if success {
+            let download_path = if canonical_language(language) == Some("java") {
                 "/tmp/java_bundle.tar"
             } else {
                 "/tmp/main"
             };
-            self.download_file(&container_name, download_path)
-                .await
-                .unwrap_or_default()
-        } else {
-            vec![]
-        };
+            match self.download_file(&container_name, download_path).await {
+                Ok(binary) => binary_payload = binary,
+                Err(e) => {
+                    success = false;
+                    compiler_output.push_str(&format!("\nFailed to collect the build output: {}", e));
+                }
+            }
+        }
 
         // Cleanup
-        let _ = self.cleanup_container(&container_name).await;
+        self.pool.release(&container_name).await;
 
         CompileResult {
             job_id: job_id.to_string(),
+            checker,
             success,
             compiler_output,
             binary_payload,
@@ -193,251 +422,177 @@ impl DockerExecutor {
         }
     }
 
-    /// Execute a batch of test cases
+    /// Execute a batch of test cases, sending each result on `progress` as soon
+    /// as it is produced
     pub async fn execute_batch(
         &self,
-        job_id: &str,
-        batch_id: &str,
         worker_id: &str,
-        language: &str,
-        binary: Option<&[u8]>,
-        source_code: Option<&str>,
-        test_cases: &[TestCase],
-        time_limit_ms: u32,
-        memory_limit_mb: u32,
+        task: &ExecuteBatchTask,
+        progress: &mpsc::Sender<TestCaseResult>,
     ) -> BatchExecutionResult {
+        let job_id = task.job_id.as_str();
+        let batch_id = task.batch_id.as_str();
+        let test_cases = &task.inputs;
+        let time_limit_ms = task.time_limit_ms;
+        let memory_limit_mb = task.memory_limit_mb;
+        let isolation = task.isolation();
+
+        // Payload: binary for compiled languages, source for interpreted ones
+        let (binary, source_code) = match &task.payload {
+            Some(execute_batch_task::Payload::BinaryArtifact(b)) => (Some(b.as_slice()), None),
+            Some(execute_batch_task::Payload::SourceCode(s)) => (None, Some(s.as_str())),
+            None => (None, None),
+        };
+
         let mut results = Vec::new();
-        let peak_ram: u64 = 0;
+        let mut peak_ram: u64 = 0;
         let mut total_cpu_time: u64 = 0;
 
-        // Determine execution method based on language
-        // Interpreted languages run source directly; compiled languages run binaries
-        let is_interpreted = matches!(
-            language.to_lowercase().as_str(),
-            "python" | "python3" | "javascript" | "js" | "node" | "ruby"
-        );
-        
-        // Java is special - compiled but runs on JVM
-        let is_java = matches!(language.to_lowercase().as_str(), "java");
+        let system_failure = |system_error: String| BatchExecutionResult {
+            job_id: job_id.to_string(),
+            batch_id: batch_id.to_string(),
+            worker_id: worker_id.to_string(),
+            results: vec![],
+            metrics: Some(ResourceMetrics {
+                peak_ram_bytes: 0,
+                total_cpu_time_ms: 0,
+            }),
+            system_error,
+        };
 
-        // Create container
-        let container_name = format!("run_{}_{}", job_id.replace('-', "_"), batch_id);
-        let image = if is_java {
-            "eclipse-temurin:25"
-        } else if is_interpreted {
-            match language.to_lowercase().as_str() {
-                "python" | "python3" => "python:3-slim",
-                "javascript" | "js" | "node" => "node:slim",
-                "ruby" => "ruby:slim",
-                _ => "alpine:latest",
-            }
-        } else {
-            "debian:bookworm-slim"
+        // Built before the first container starts, so no batch waits on it
+        let runner = self.runner().await;
+
+        let Some(language) = canonical_language(&task.language) else {
+            return system_failure(format!("Unsupported language: {}", task.language));
         };
 
-        let config = Config {
-            image: Some(image.to_string()),
-            cmd: Some(vec!["sleep".to_string(), "300".to_string()]),
-            host_config: Some(bollard::service::HostConfig {
-                memory: Some((memory_limit_mb as i64) * 1024 * 1024),
-                nano_cpus: Some(1_000_000_000), // 1 CPU
-                network_mode: Some("none".to_string()),
-                pids_limit: Some(50),
-                readonly_rootfs: Some(false), // Need write for /tmp
-                ..Default::default()
-            }),
-            ..Default::default()
+        // Interpreted languages run their source directly, compiled languages
+        // their binary (Java a class bundle, on the JVM)
+        let script = match language {
+            "python" => Some(("main.py", "python /box/main.py")),
+            "javascript" => Some(("main.js", "node /box/main.js")),
+            "ruby" => Some(("main.rb", "ruby /box/main.rb")),
+            _ => None,
         };
 
-        if let Err(e) = self
-            .docker
-            .create_container(
-                Some(CreateContainerOptions {
-                    name: container_name.clone(),
-                    platform: None,
-                }),
-                config,
-            )
-            .await
-        {
-            return BatchExecutionResult {
-                job_id: job_id.to_string(),
-                batch_id: batch_id.to_string(),
-                worker_id: worker_id.to_string(),
-                results: vec![],
-                metrics: Some(ResourceMetrics {
-                    peak_ram_bytes: 0,
-                    total_cpu_time_ms: 0,
-                }),
-                system_error: format!("Failed to create container: {}", e),
-            };
-        }
+        let container_name = format!("run_{}_{}", job_id.replace('-', "_"), batch_id);
+        let image = run_image(language).unwrap_or("alpine:latest");
 
-        // Start container
-        if let Err(e) = self
-            .docker
-            .start_container(&container_name, None::<StartContainerOptions<String>>)
-            .await
-        {
-            let _ = self.cleanup_container(&container_name).await;
-            return BatchExecutionResult {
-                job_id: job_id.to_string(),
-                batch_id: batch_id.to_string(),
-                worker_id: worker_id.to_string(),
-                results: vec![],
-                metrics: Some(ResourceMetrics {
-                    peak_ram_bytes: 0,
-                    total_cpu_time_ms: 0,
-                }),
-                system_error: format!("Failed to start container: {}", e),
-            };
-        }
+        // Executable or source
+        let program = Program {
+            source: script.zip(source_code).map(|((filename, _), src)| (filename, src)),
+            binary: binary.filter(|_| script.is_none()),
+            is_java: language == "java",
+            runner,
+        };
 
-        // Upload executable or source
-        if is_interpreted {
-            if let Some(src) = source_code {
-                let (filename, _) = match language.to_lowercase().as_str() {
-                    "python" | "python3" => ("main.py", "python /tmp/main.py"),
-                    "javascript" | "js" | "node" => ("main.js", "node /tmp/main.js"),
-                    "ruby" => ("main.rb", "ruby /tmp/main.rb"),
-                    _ => ("main.txt", "cat /tmp/main.txt"),
-                };
-                let tar_data = create_tar_archive(filename, src.as_bytes());
-                let _ = self
-                    .docker
-                    .upload_to_container(
-                        &container_name,
-                        Some(UploadToContainerOptions {
-                            path: "/tmp",
-                            ..Default::default()
-                        }),
-                        tar_data.into(),
-                    )
-                    .await;
-            }
-        } else if let Some(bin) = binary {
-            // For Java, the binary is actually a tarball containing classes and wrapper
-            if is_java {
-                // Upload the tarball directly (it's already a tar archive)
-                let _ = self
-                    .docker
-                    .upload_to_container(
-                        &container_name,
-                        Some(UploadToContainerOptions {
-                            path: "/tmp",
-                            ..Default::default()
-                        }),
-                        bin.to_vec().into(),
-                    )
-                    .await;
-                // Extract the bundle: creates /tmp/classes/* and /tmp/main
-                let _ = self
-                    .exec_in_container(
-                        &container_name,
-                        "mkdir -p /tmp/classes && cd /tmp && tar -xf /tmp/java_bundle.tar && chmod +x /tmp/main",
-                        Duration::from_secs(10),
-                    )
-                    .await;
-            } else {
-                let tar_data = create_tar_archive_executable("main", bin);
-                let _ = self
-                    .docker
-                    .upload_to_container(
-                        &container_name,
-                        Some(UploadToContainerOptions {
-                            path: "/tmp",
-                            ..Default::default()
-                        }),
-                        tar_data.into(),
-                    )
-                    .await;
-                // Make executable
-                let _ = self
-                    .exec_in_container(&container_name, "chmod +x /tmp/main", Duration::from_secs(5))
-                    .await;
+        // In shared mode one container runs every test; otherwise each test
+        // gets its own below
+        if isolation == Isolation::Shared {
+            if let Err(e) = self
+                .start_sandbox(&container_name, image, memory_limit_mb, &program, false)
+                .await
+            {
+                return system_failure(e);
             }
         }
 
         // Execute each test case
-        let exec_cmd = if is_java {
-            // Java runs the compiled .class file
-            "java -cp /tmp Main"
-        } else if is_interpreted {
-            match language.to_lowercase().as_str() {
-                "python" | "python3" => "python /tmp/main.py",
-                "javascript" | "js" | "node" => "node /tmp/main.js",
-                "ruby" => "ruby /tmp/main.rb",
-                _ => "/tmp/main",
-            }
-        } else {
-            "/tmp/main"
+        let exec_cmd = match script {
+            Some((_, cmd)) => cmd,
+            None => binary_command(language),
         };
 
-        for tc in test_cases {
-            let start = Instant::now();
+        // Under the runner, time limits apply to CPU time; wall-clock time
+        // only stops programs that block
+        let time_limit = Duration::from_millis(time_limit_ms as u64);
+        let wall_limit = match runner {
+            Some(_) => time_limit * WALL_TIME_FACTOR + Duration::from_secs(1),
+            None => time_limit,
+        };
+        // The runner replaces the shell, so it is the only process in the
+        // container besides PID 1 and can clear out whatever a test leaves
+        let run_cmd = match runner {
+            Some(_) => format!(
+                "exec {} {} {} '{}'",
+                RUNNER_PATH,
+                time_limit_ms,
+                wall_limit.as_millis(),
+                exec_cmd
+            ),
+            None => exec_cmd.to_string(),
+        };
+        let spec = RunSpec {
+            cmd: &run_cmd,
+            measured: runner.is_some(),
+            time_limit_ms,
+            memory_limit_mb,
+            wall_limit,
+            program: &program,
+        };
 
-            let result = self
-                .run_with_input(
-                    &container_name,
-                    exec_cmd,
-                    &tc.input,
-                    Duration::from_millis(time_limit_ms as u64),
-                )
-                .await;
+        // The checker (or interactor) gets a container of its own so the
+        // submission cannot tamper with it
+        let checker_container = format!("{}_checker", container_name);
+        if let Some(checker) = &task.checker {
+            if let Err(e) = self.start_checker(&checker_container, checker).await {
+                self.pool.release(&checker_container).await;
+                self.pool.release(&container_name).await;
+                return system_failure(e);
+            }
+        }
 
-            let elapsed_ms = start.elapsed().as_millis() as i32;
-            total_cpu_time += elapsed_ms as u64;
-
-            let tc_result = match result {
-                Ok((exit_code, stdout, stderr)) => {
-                    let actual_output = stdout.trim();
-                    let expected_output = tc.expected_output.trim();
-
-                    // Detect Memory Limit Exceeded:
-                    // - Exit code 137 = 128 + 9 (SIGKILL from OOM killer)
-                    // - "Killed" in output indicates OOM
-                    let is_mle = exit_code == 137
-                        || stdout.contains("Killed")
-                        || stderr.contains("Killed")
-                        || stderr.contains("Out of memory");
-
-                    let status = if is_mle {
-                        "MLE" // Memory Limit Exceeded
-                    } else if exit_code != 0 {
-                        "RE" // Runtime Error
-                    } else if actual_output == expected_output {
-                        "PASSED"
-                    } else {
-                        "FAILED"
-                    };
-
-                    TestCaseResult {
-                        test_id: tc.id.clone(),
-                        status: status.to_string(),
-                        stdout,
-                        stderr,
-                        time_ms: elapsed_ms,
-                        memory_bytes: 0, // TODO: get actual memory usage
-                    }
+        for (index, tc) in test_cases.iter().enumerate() {
+            let tc_result = match isolation {
+                Isolation::Shared => {
+                    self.run_test(&container_name, &spec, task, &checker_container, tc)
+                        .await
                 }
-                Err(e) => {
-                    let status = if e.contains("timeout") { "TLE" } else { "RE" };
-                    TestCaseResult {
-                        test_id: tc.id.clone(),
-                        status: status.to_string(),
-                        stdout: String::new(),
-                        stderr: e,
-                        time_ms: elapsed_ms,
-                        memory_bytes: 0,
+                // Nothing a test writes, starts or allocates outlives it: its
+                // container is created for it, not taken from a pool
+                Isolation::PerTest => {
+                    let test_container = format!("{}_test{}", container_name, index + 1);
+                    match self
+                        .start_sandbox(&test_container, image, memory_limit_mb, &program, true)
+                        .await
+                    {
+                        Ok(()) => {
+                            let tc_result = self
+                                .run_test(&test_container, &spec, task, &checker_container, tc)
+                                .await;
+                            self.pool.release(&test_container).await;
+                            tc_result
+                        }
+                        // Only this test is lost; the ones before it have been
+                        // reported already and the rest may still run
+                        Err(e) => TestCaseResult {
+                            test_id: tc.id.clone(),
+                            status: "SYSTEM_ERROR".to_string(),
+                            stdout: vec![],
+                            stderr: e,
+                            time_ms: 0,
+                            memory_bytes: 0,
+                            score: 0.0,
+                            checker_message: String::new(),
+                        },
                     }
                 }
             };
+            total_cpu_time += tc_result.time_ms.max(0) as u64;
+            peak_ram = peak_ram.max(tc_result.memory_bytes.max(0) as u64);
 
+            let _ = progress.send(tc_result.clone()).await;
             results.push(tc_result);
         }
 
         // Cleanup
-        let _ = self.cleanup_container(&container_name).await;
+        if task.checker.is_some() {
+            self.pool.release(&checker_container).await;
+        }
+        if isolation == Isolation::Shared {
+            self.pool.release(&container_name).await;
+        }
 
         BatchExecutionResult {
             job_id: job_id.to_string(),
@@ -452,19 +607,332 @@ impl DockerExecutor {
         }
     }
 
-    /// Execute a command in a container with timeout
-    async fn exec_in_container(
+    /// Take a run container, or create one that is never pooled if `fresh`,
+    /// then install the program in it
+    async fn start_sandbox(
+        &self,
+        name: &str,
+        image: &str,
+        memory_limit_mb: u32,
+        program: &Program<'_>,
+        fresh: bool,
+    ) -> Result<(), String> {
+        if fresh {
+            self.pool
+                .checkout_fresh(PoolKind::Run, image, name, memory_limit_mb)
+                .await?;
+        } else {
+            self.pool
+                .checkout(PoolKind::Run, image, name, memory_limit_mb)
+                .await?;
+        }
+
+        if let Err(e) = self.install(name, program).await {
+            self.pool.release(name).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Install a program in a run container's program directory
+    async fn install(&self, container: &str, program: &Program<'_>) -> Result<(), String> {
+        if let Some((filename, src)) = program.source {
+            self.unpack(
+                container,
+                PROGRAM_DIR,
+                create_tar_archive(filename, src.as_bytes())?,
+            )
+            .await?;
+        } else if let Some(bin) = program.binary {
+            // Java artifacts are already a tar of the classes
+            let archive = if program.is_java {
+                bin.to_vec()
+            } else {
+                create_tar_archive_executable("main", bin)?
+            };
+            self.unpack(container, PROGRAM_DIR, archive).await?;
+        }
+        if let Some(runner) = program.runner {
+            self.unpack(
+                container,
+                PROGRAM_DIR,
+                create_tar_archive_executable(".runner", runner)?,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Unpack a tar archive into a directory of a run container. Its writable
+    /// directories are tmpfs mounts, which Docker cannot copy into, so `tar`
+    /// runs inside the container, as `INSTALL_USER` so that programs cannot
+    /// change what is unpacked.
+    async fn unpack(&self, container: &str, dir: &str, archive: Vec<u8>) -> Result<(), String> {
+        let cmd = format!("tar -xof - -C {}", dir);
+        let (exit_code, _, stderr) = self
+            .run_with_input(container, &cmd, Some(INSTALL_USER), archive, UNPACK_TIMEOUT)
+            .await?;
+        if exit_code != 0 {
+            return Err(format!("Failed to unpack files: {}", stderr.trim()));
+        }
+        Ok(())
+    }
+
+    /// Run and judge one test in a run container
+    async fn run_test(
+        &self,
+        container: &str,
+        spec: &RunSpec<'_>,
+        task: &ExecuteBatchTask,
+        checker_container: &str,
+        tc: &TestCase,
+    ) -> TestCaseResult {
+        if let Some(interactor) = task.checker.as_ref().filter(|c| c.interactive) {
+            return self
+                .run_interactive(container, spec, checker_container, interactor, tc)
+                .await;
+        }
+
+        let start = Instant::now();
+
+        let result = self
+            .run_with_input(container, spec.cmd, None, tc.input.clone(), spec.deadline())
+            .await;
+        let elapsed = start.elapsed();
+
+        match result {
+            Ok((exit_code, stdout, mut stderr)) => {
+                let comparator = tc.comparator.as_ref().or(task.comparator.as_ref());
+                let outcome = spec.outcome(exit_code, &mut stderr, elapsed);
+
+                let verdict = if let Some(status) = outcome.failure {
+                    Verdict::failed(status)
+                } else if let Some(checker) = &task.checker {
+                    self.run_checker(checker_container, checker, tc, &stdout)
+                        .await
+                } else if outputs_match(comparator, &stdout, &tc.expected_output) {
+                    Verdict::passed()
+                } else {
+                    Verdict::failed("FAILED")
+                };
+
+                TestCaseResult {
+                    test_id: tc.id.clone(),
+                    status: verdict.status.to_string(),
+                    stdout,
+                    stderr,
+                    time_ms: saturating_i32(outcome.time_ms),
+                    memory_bytes: saturating_i32(outcome.peak_bytes),
+                    score: verdict.score,
+                    checker_message: verdict.message,
+                }
+            }
+            Err(e) => {
+                let timed_out = matches!(e, ExecError::Timeout);
+                if timed_out {
+                    // Whatever the test started may still be running
+                    self.reset_container(container, spec.program).await;
+                }
+                let status = if timed_out { "TLE" } else { "RE" };
+                TestCaseResult {
+                    test_id: tc.id.clone(),
+                    status: status.to_string(),
+                    stdout: vec![],
+                    stderr: e.to_string(),
+                    time_ms: saturating_i32(elapsed.as_millis() as u64),
+                    memory_bytes: 0,
+                    score: 0.0,
+                    checker_message: String::new(),
+                }
+            }
+        }
+    }
+
+    /// Start a batch's checker (or interactor) container and install it there
+    async fn start_checker(&self, container: &str, checker: &Checker) -> Result<(), String> {
+        let image = run_image(&checker.language).unwrap_or("alpine:latest");
+        self.pool
+            .checkout(PoolKind::Run, image, container, CHECKER_MEMORY_MB)
+            .await
+            .map_err(|e| format!("Checker container: {}", e))?;
+
+        self.install(container, &Program::checker(checker))
+            .await
+            .map_err(|e| format!("Failed to install checker: {}", e))
+    }
+
+    /// Run the checker on one test's contestant output
+    async fn run_checker(
+        &self,
+        container: &str,
+        checker: &Checker,
+        tc: &TestCase,
+        output: &[u8],
+    ) -> Verdict {
+        let uploaded = match create_tar_archive_files(&[
+            ("input.txt", &tc.input),
+            ("output.txt", output),
+            ("answer.txt", tc.expected_output.as_bytes()),
+        ]) {
+            Ok(files) => self.unpack(container, SCRATCH_DIR, files).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = uploaded {
+            return Verdict {
+                status: "CHECKER_ERROR",
+                score: 0.0,
+                message: format!("Failed to upload checker files: {}", e),
+            };
+        }
+
+        let cmd = format!(
+            "cd {} && {} input.txt output.txt answer.txt",
+            SCRATCH_DIR,
+            binary_command(&checker.language)
+        );
+        match self
+            .exec_in_container(container, &cmd, checker_timeout(checker))
+            .await
+        {
+            Ok((exit_code, output)) => checker_verdict(exit_code, &output),
+            Err(e) => {
+                if matches!(e, ExecError::Timeout) {
+                    self.reset_container(container, &Program::checker(checker))
+                        .await;
+                }
+                Verdict {
+                    status: "CHECKER_ERROR",
+                    score: 0.0,
+                    message: e.to_string(),
+                }
+            }
+        }
+    }
+
+    /// Run one test of an interactive problem: the submission and the
+    /// interactor run side by side, each one's stdout fed to the other's stdin,
+    /// each under its own time limit
+    async fn run_interactive(
+        &self,
+        container: &str,
+        spec: &RunSpec<'_>,
+        interactor_container: &str,
+        interactor: &Checker,
+        tc: &TestCase,
+    ) -> TestCaseResult {
+        let start = Instant::now();
+        let result =
+            |verdict: Verdict, stdout: Vec<u8>, stderr: String, elapsed: Duration| TestCaseResult {
+                test_id: tc.id.clone(),
+                status: verdict.status.to_string(),
+                stdout,
+                stderr,
+                time_ms: elapsed.as_millis() as i32,
+                memory_bytes: 0,
+                score: verdict.score,
+                checker_message: verdict.message,
+            };
+        let interactor_error = |message: String| Verdict {
+            status: "CHECKER_ERROR",
+            score: 0.0,
+            message,
+        };
+
+        let uploaded = match create_tar_archive_files(&[
+            ("input.txt", &tc.input),
+            ("answer.txt", tc.expected_output.as_bytes()),
+        ]) {
+            Ok(files) => self.unpack(interactor_container, SCRATCH_DIR, files).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = uploaded {
+            let message = format!("Failed to upload interactor files: {}", e);
+            return result(
+                interactor_error(message),
+                vec![],
+                String::new(),
+                start.elapsed(),
+            );
+        }
+
+        let interactor_cmd = format!(
+            "cd {} && {} input.txt answer.txt",
+            SCRATCH_DIR,
+            binary_command(&interactor.language)
+        );
+        let (solution, judge) = match tokio::try_join!(
+            self.start_attached(container, spec.cmd, None),
+            self.start_attached(interactor_container, &interactor_cmd, None),
+        ) {
+            Ok(execs) => execs,
+            Err(e) => return result(interactor_error(e), vec![], String::new(), start.elapsed()),
+        };
+        let (solution_id, solution_output, solution_input) = solution;
+        let (judge_id, judge_output, judge_input) = judge;
+
+        let (solution_side, judge_side) = tokio::join!(
+            async {
+                let side = timeout(spec.deadline(), pump(solution_output, judge_input)).await;
+                (side, start.elapsed())
+            },
+            timeout(
+                checker_timeout(interactor),
+                pump(judge_output, solution_input)
+            ),
+        );
+        let (solution_side, elapsed) = solution_side;
+
+        // Neither side may be left running into the next test
+        if judge_side.is_err() {
+            self.reset_container(interactor_container, &Program::checker(interactor))
+                .await;
+        }
+        let Ok((stdout, mut stderr)) = solution_side else {
+            self.reset_container(container, spec.program).await;
+            let stderr = "Execution timeout".to_string();
+            return result(Verdict::failed("TLE"), vec![], stderr, elapsed);
+        };
+
+        let verdict = match judge_side {
+            Ok((_, judge_stderr)) => {
+                checker_verdict(self.exit_code(&judge_id).await.unwrap_or(-1), &judge_stderr)
+            }
+            Err(_) => interactor_error("Interactor timeout".to_string()),
+        };
+
+        // The interactor rejecting the submission takes precedence over the
+        // submission crashing (e.g. on a closed pipe) after it did
+        let exit_code = self.exit_code(&solution_id).await.unwrap_or(-1);
+        let outcome = spec.outcome(exit_code, &mut stderr, elapsed);
+        let verdict = match outcome.failure {
+            Some(status @ ("TLE" | "MLE")) => Verdict::failed(status),
+            Some(_) if matches!(verdict.status, "PASSED" | "PARTIAL") => Verdict::failed("RE"),
+            _ => verdict,
+        };
+
+        TestCaseResult {
+            time_ms: saturating_i32(outcome.time_ms),
+            memory_bytes: saturating_i32(outcome.peak_bytes),
+            ..result(verdict, stdout, stderr, elapsed)
+        }
+    }
+
+    /// Start a command in a container with stdin, stdout and stderr attached,
+    /// as `user` or else the container's user
+    async fn start_attached(
         &self,
         container: &str,
         cmd: &str,
-        timeout_duration: Duration,
-    ) -> Result<(i64, String), String> {
+        user: Option<&str>,
+    ) -> Result<(String, ExecOutput, ExecInput), String> {
         let exec = self
             .docker
             .create_exec(
                 container,
                 CreateExecOptions {
                     cmd: Some(vec!["sh", "-c", cmd]),
+                    user,
+                    attach_stdin: Some(true),
                     attach_stdout: Some(true),
                     attach_stderr: Some(true),
                     ..Default::default()
@@ -473,6 +941,79 @@ impl DockerExecutor {
             .await
             .map_err(|e| format!("Failed to create exec: {}", e))?;
 
+        match self.docker.start_exec(&exec.id, None).await {
+            Ok(StartExecResults::Attached { output, input }) => Ok((exec.id, output, input)),
+            Ok(StartExecResults::Detached) => Err("Exec started detached".to_string()),
+            Err(e) => Err(format!("Exec failed: {}", e)),
+        }
+    }
+
+    /// Exit code of a finished exec
+    async fn exit_code(&self, exec_id: &str) -> Option<i64> {
+        self.docker.inspect_exec(exec_id).await.ok()?.exit_code
+    }
+
+    /// Execute a shell command in a container with timeout
+    async fn exec_in_container(
+        &self,
+        container: &str,
+        cmd: &str,
+        timeout_duration: Duration,
+    ) -> Result<(i64, String), ExecError> {
+        self.exec_argv(container, &["sh", "-c", cmd], None, timeout_duration)
+            .await
+    }
+
+    /// Run build steps in order until one fails, all within `timeout_duration`;
+    /// returns the exit code of the last step run and the output of every step
+    async fn run_build(
+        &self,
+        container: &str,
+        steps: &[Argv],
+        working_dir: &str,
+        timeout_duration: Duration,
+    ) -> Result<(i64, String), ExecError> {
+        let deadline = Instant::now() + timeout_duration;
+        let mut output = String::new();
+        let mut exit_code = 0;
+        for step in steps {
+            let argv: Vec<&str> = step.iter().map(String::as_str).collect();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let (code, step_output) = self
+                .exec_argv(container, &argv, Some(working_dir), remaining)
+                .await?;
+            output.push_str(&step_output);
+            exit_code = code;
+            if exit_code != 0 {
+                break;
+            }
+        }
+        Ok((exit_code, output))
+    }
+
+    /// Execute a command in a container, without a shell, with timeout
+    async fn exec_argv(
+        &self,
+        container: &str,
+        argv: &[&str],
+        working_dir: Option<&str>,
+        timeout_duration: Duration,
+    ) -> Result<(i64, String), ExecError> {
+        let exec = self
+            .docker
+            .create_exec(
+                container,
+                CreateExecOptions {
+                    cmd: Some(argv.to_vec()),
+                    working_dir,
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| ExecError::Failed(format!("Failed to create exec: {}", e)))?;
+
         let output = timeout(timeout_duration, async {
             match self.docker.start_exec(&exec.id, None).await {
                 Ok(StartExecResults::Attached { mut output, .. }) => {
@@ -490,102 +1031,459 @@ impl DockerExecutor {
                     Ok((exit_code, result))
                 }
                 Ok(StartExecResults::Detached) => Ok((0, String::new())),
-                Err(e) => Err(format!("Exec failed: {}", e)),
+                Err(e) => Err(ExecError::Failed(format!("Exec failed: {}", e))),
             }
         })
         .await
-        .map_err(|_| "Execution timeout".to_string())??;
+        .map_err(|_| ExecError::Timeout)??;
 
         Ok(output)
     }
 
-    /// Run a command with stdin input
+    /// Run a command (as `user`, if given) with `input` streamed to its stdin
+    /// as is; returns its exit code, stdout and stderr
     async fn run_with_input(
         &self,
         container: &str,
         cmd: &str,
-        input: &str,
+        user: Option<&str>,
+        input: Vec<u8>,
         timeout_duration: Duration,
-    ) -> Result<(i64, String, String), String> {
-        // Write input to a file and pipe it
-        let input_escaped = input.replace("'", "'\\''");
-        let full_cmd = format!("echo '{}' | {}", input_escaped, cmd);
+    ) -> Result<(i64, Vec<u8>, String), ExecError> {
+        let (exec_id, mut output, mut stdin) = self
+            .start_attached(container, cmd, user)
+            .await
+            .map_err(ExecError::Failed)?;
 
-        let (exit_code, output) = self
-            .exec_in_container(container, &full_cmd, timeout_duration)
-            .await?;
+        // Feed stdin while the output is drained, so neither side can block
+        // on a full pipe; the program may exit without reading all of it
+        let feeder = tokio::spawn(async move {
+            let _ = stdin.write_all(&input).await;
+            let _ = stdin.shutdown().await;
+        });
 
-        // Try to split stdout/stderr (simplified - real implementation would capture separately)
-        Ok((exit_code, output, String::new()))
+        let collected = timeout(timeout_duration, async {
+            let mut stdout = Vec::new();
+            let mut stderr = Vec::new();
+            while let Some(chunk) = output.next().await {
+                match chunk {
+                    Ok(LogOutput::StdOut { message }) => stdout.extend_from_slice(&message),
+                    Ok(LogOutput::StdErr { message }) => stderr.extend_from_slice(&message),
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+            (stdout, stderr)
+        })
+        .await;
+        feeder.abort();
+
+        let (stdout, stderr) = collected.map_err(|_| ExecError::Timeout)?;
+        let exit_code = self.exit_code(&exec_id).await.unwrap_or(-1);
+        let stderr = String::from_utf8_lossy(&stderr).into_owned();
+        Ok((exit_code, stdout, stderr))
     }
 
     /// Download a file from container
     async fn download_file(&self, container: &str, path: &str) -> Result<Vec<u8>, String> {
         let stream = self
             .docker
-            .download_from_container(container, Some(bollard::container::DownloadFromContainerOptions { path }))
-            .map(|chunk| chunk.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)));
+            .download_from_container(container, Some(bollard::container::DownloadFromContainerOptions { path }));
 
-        let bytes: Vec<u8> = tokio_stream::StreamExt::collect::<Vec<_>>(stream)
-            .await
-            .into_iter()
-            .filter_map(|r| r.ok())
-            .flatten()
-            .collect();
+        // Stop reading an artifact that grew too large, rather than hold it
+        let mut stream = std::pin::pin!(stream);
+        let mut bytes = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            let Ok(chunk) = chunk else { continue };
+            if bytes.len() + chunk.len() > MAX_ARTIFACT_BYTES {
+                return Err(format!("{} is larger than {} bytes", path, MAX_ARTIFACT_BYTES));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
 
         // Extract from tar
         extract_from_tar(&bytes).ok_or_else(|| "Failed to extract file from tar".to_string())
     }
 
-    /// Remove a container
-    async fn cleanup_container(&self, name: &str) -> Result<(), String> {
-        self.docker
-            .remove_container(
-                name,
-                Some(RemoveContainerOptions {
-                    force: true,
-                    ..Default::default()
-                }),
-            )
+    /// Probe which languages this worker can compile and run, based on which of
+    /// the images used by `compile` and `execute_batch` are present locally
+    pub async fn probe_capabilities(&self) -> (Vec<String>, Vec<String>) {
+        let mut present: HashMap<&str, bool> = HashMap::new();
+        let mut compile_languages = Vec::new();
+        let mut run_languages = Vec::new();
+        let mut warm = Vec::new();
+
+        for &language in LANGUAGES {
+            if let Some(image) = compile_image(language) {
+                if self.has_image(image, &mut present).await {
+                    compile_languages.push(language.to_string());
+                    warm.push((PoolKind::Build, image));
+                }
+            }
+            if let Some(image) = run_image(language) {
+                if self.has_image(image, &mut present).await {
+                    run_languages.push(language.to_string());
+                    warm.push((PoolKind::Run, image));
+                }
+            }
+        }
+
+        // Keep a pool per image that is present
+        warm.sort_by_key(|&(kind, image)| (kind.as_str(), image));
+        warm.dedup();
+        self.pool.keep_warm(warm);
+
+        (compile_languages, run_languages)
+    }
+
+    /// Check whether an image exists locally, caching the answer in `present`
+    async fn has_image<'a>(&self, image: &'a str, present: &mut HashMap<&'a str, bool>) -> bool {
+        if let Some(&found) = present.get(image) {
+            return found;
+        }
+
+        let found = self.docker.inspect_image(image).await.is_ok();
+        if !found {
+            warn!(image = %image, "Docker image not found locally");
+        }
+        present.insert(image, found);
+        found
+    }
+
+    /// Force-remove every container of a job: its compile container and the
+    /// containers of all its batches. Returns how many were removed.
+    pub async fn remove_job_containers(&self, job_id: &str) -> usize {
+        let key = job_id.replace('-', "_");
+        let compile_name = format!("/compile_{}", key);
+        let checker_compile_name = format!("/compile_{}_checker", key);
+        let run_prefix = format!("/run_{}_", key);
+
+        let mut filters = HashMap::new();
+        filters.insert("name".to_string(), vec![key]);
+
+        let containers = match self
+            .docker
+            .list_containers(Some(ListContainersOptions {
+                all: true,
+                filters,
+                ..Default::default()
+            }))
+            .await
+        {
+            Ok(containers) => containers,
+            Err(e) => {
+                warn!(job_id = %job_id, error = %e, "Failed to list job containers");
+                return 0;
+            }
+        };
+
+        let mut removed = 0;
+        for name in containers
+            .into_iter()
+            .flat_map(|c| c.names.unwrap_or_default())
+        {
+            if name != compile_name
+                && name != checker_compile_name
+                && !name.starts_with(&run_prefix)
+            {
+                continue;
+            }
+            match self.pool.remove(name.trim_start_matches('/')).await {
+                Ok(()) => removed += 1,
+                Err(e) => warn!(container = %name, error = %e, "Failed to remove job container"),
+            }
+        }
+        removed
+    }
+
+    /// Kill every process in a run container by restarting it. The restart
+    /// also empties its tmpfs mounts, so the program is installed again.
+    async fn reset_container(&self, container: &str, program: &Program<'_>) {
+        let reset = match self
+            .docker
+            .restart_container(container, Some(RestartContainerOptions { t: 0 }))
             .await
-            .map_err(|e| format!("Failed to remove container: {}", e))
+        {
+            Ok(()) => self.install(container, program).await,
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = reset {
+            warn!(container = %container, error = %e, "Failed to reset container");
+        }
+    }
+}
+
+/// Image used to compile `language`, or `None` for interpreted/unknown languages
+pub fn compile_image(language: &str) -> Option<&'static str> {
+    match canonical_language(language)? {
+        "c" | "cpp" => Some("gcc:latest"),
+        "rust" => Some("rust:latest"),
+        "go" => Some("golang:latest"),
+        "java" => Some("eclipse-temurin:25"),
+        _ => None,
     }
 }
 
-/// Create a tar archive containing a single file
-fn create_tar_archive(filename: &str, content: &[u8]) -> Vec<u8> {
+/// Image used to run `language` (the compiled binary, or the source for interpreted languages)
+pub fn run_image(language: &str) -> Option<&'static str> {
+    match canonical_language(language)? {
+        "c" | "cpp" | "rust" | "go" => Some("debian:bookworm-slim"),
+        "java" => Some("eclipse-temurin:25"),
+        "python" => Some("python:3-slim"),
+        "javascript" => Some("node:slim"),
+        "ruby" => Some("ruby:slim"),
+        _ => None,
+    }
+}
 
-    let mut header = tar::Header::new_gnu();
-    header.set_path(filename).unwrap();
-    header.set_size(content.len() as u64);
-    header.set_mode(0o644);
-    header.set_cksum();
+/// Forward a process's stdout to its peer's stdin until it exits, then close
+/// the peer's stdin. Returns what the process wrote to stdout and stderr.
+async fn pump(mut output: ExecOutput, mut peer_input: ExecInput) -> (Vec<u8>, String) {
+    let mut stdout = Vec::new();
+    let mut stderr = String::new();
+    let mut peer_open = true;
 
-    let mut archive = Vec::new();
-    {
-        let mut builder = tar::Builder::new(&mut archive);
-        builder.append(&header, content).unwrap();
-        builder.finish().unwrap();
+    while let Some(chunk) = output.next().await {
+        match chunk {
+            Ok(LogOutput::StdOut { message }) => {
+                // Keep draining after the peer went away so the process is not blocked
+                if peer_open {
+                    peer_open = peer_input.write_all(&message).await.is_ok()
+                        && peer_input.flush().await.is_ok();
+                }
+                stdout.extend_from_slice(&message);
+            }
+            Ok(LogOutput::StdErr { message }) => {
+                stderr.push_str(&String::from_utf8_lossy(&message));
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
     }
-    archive
+
+    let _ = peer_input.shutdown().await;
+    (stdout, stderr)
 }
 
-/// Create a tar archive with an executable file
-fn create_tar_archive_executable(filename: &str, content: &[u8]) -> Vec<u8> {
+/// Take the runner's report (see runner.c) off the end of a run's stderr
+fn take_usage(stderr: &mut String) -> Option<Usage> {
+    let at = stderr.rfind(USAGE_MARKER)?;
+    let fields: Vec<i64> = stderr[at + USAGE_MARKER.len()..]
+        .split_whitespace()
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    let [exit_code, cpu_ms, peak_kb, timed_out, oom_killed] = fields[..] else {
+        return None;
+    };
 
-    let mut header = tar::Header::new_gnu();
-    header.set_path(filename).unwrap();
-    header.set_size(content.len() as u64);
-    header.set_mode(0o755);
-    header.set_cksum();
+    stderr.truncate(at);
+    Some(Usage {
+        exit_code,
+        cpu_ms: cpu_ms.max(0) as u64,
+        peak_bytes: peak_kb.max(0) as u64 * 1024,
+        timed_out: timed_out != 0,
+        oom_killed: oom_killed != 0,
+    })
+}
+
+/// Convert a measurement for an `int32` proto field, saturating
+fn saturating_i32(value: u64) -> i32 {
+    i32::try_from(value).unwrap_or(i32::MAX)
+}
 
-    let mut archive = Vec::new();
-    {
-        let mut builder = tar::Builder::new(&mut archive);
-        builder.append(&header, content).unwrap();
-        builder.finish().unwrap();
+/// Per-test time limit of a checker or interactor
+fn checker_timeout(checker: &Checker) -> Duration {
+    match checker.time_limit_ms {
+        0 => DEFAULT_CHECKER_TIMEOUT,
+        ms => Duration::from_millis(ms as u64),
     }
-    archive
+}
+
+/// Source file name and build steps of a single-file submission uploaded to
+/// /tmp; the steps leave the artifact where `compile` collects it: /tmp/main,
+/// or for Java the bundle made from the class directory
+fn source_build(language: &str, flags: &[String]) -> Option<(&'static str, Vec<Argv>)> {
+    let output = |source: &str| argv(&["-o", "/tmp/main", source]);
+    let build = match canonical_language(language)? {
+        "cpp" => (
+            "main.cpp",
+            vec![compiler_argv(
+                &["g++", "-static"],
+                flags,
+                output("/tmp/main.cpp"),
+            )],
+        ),
+        "c" => (
+            "main.c",
+            vec![compiler_argv(
+                &["gcc", "-static"],
+                flags,
+                output("/tmp/main.c"),
+            )],
+        ),
+        "rust" => (
+            "main.rs",
+            vec![compiler_argv(&["rustc"], flags, output("/tmp/main.rs"))],
+        ),
+        "go" => (
+            "main.go",
+            vec![argv(&["go", "build", "-o", "/tmp/main", "/tmp/main.go"])],
+        ),
+        "java" => (
+            "Main.java",
+            // Compile to /tmp/classes, then bundle the classes
+            [
+                vec![
+                    argv(&["mkdir", "-p", "/tmp/classes"]),
+                    argv(&["javac", "/tmp/Main.java", "-d", "/tmp/classes"]),
+                ],
+                java_bundle(),
+            ]
+            .concat(),
+        ),
+        _ => return None,
+    };
+    Some(build)
+}
+
+/// Build steps of a project uploaded to /tmp/src, run from there; like
+/// `source_build`'s they leave the artifact where `compile` collects it
+fn project_build(task: &CompileTask) -> Option<Vec<Argv>> {
+    let language = canonical_language(&task.language)?;
+    let flags = &task.flags;
+
+    // The project's sources with an extension; the `./` keeps file names
+    // starting with `-` from being read as options
+    let sources = |extension: &str, top_level: bool| -> Vec<String> {
+        task.files
+            .iter()
+            .map(|f| f.path.as_str())
+            .filter(|path| path.ends_with(extension) && !(top_level && path.contains('/')))
+            .map(|path| format!("./{}", path))
+            .collect()
+    };
+
+    let mut steps = match (task.build_command.as_str(), language) {
+        ("", "cpp") => vec![compiler_argv(
+            &["g++", "-static"],
+            flags,
+            [argv(&["-I.", "-o", "/tmp/main"]), sources(".cpp", false)].concat(),
+        )],
+        ("", "c") => vec![compiler_argv(
+            &["gcc", "-static"],
+            flags,
+            [argv(&["-I.", "-o", "/tmp/main"]), sources(".c", false)].concat(),
+        )],
+        ("", "rust") => {
+            let main = if task.files.iter().any(|f| f.path == "src/main.rs") {
+                "src/main.rs"
+            } else {
+                "main.rs"
+            };
+            vec![compiler_argv(
+                &["rustc"],
+                flags,
+                argv(&["-o", "/tmp/main", main]),
+            )]
+        }
+        ("", "go") => vec![[
+            argv(&["go", "build", "-o", "/tmp/main"]),
+            sources(".go", true),
+        ]
+        .concat()],
+        ("", "java") => vec![
+            argv(&["mkdir", "-p", "/tmp/classes"]),
+            [
+                argv(&["javac", "-d", "/tmp/classes"]),
+                sources(".java", false),
+            ]
+            .concat(),
+        ],
+        ("", _) => return None,
+        // One of the language's allowed build commands; it gets no
+        // compiler flags
+        (command, _) => vec![argv(build_command_argv(language, command)?)],
+    };
+
+    match (task.artifact.as_str(), language) {
+        ("", "java") | ("/tmp/classes", "java") => {}
+        (artifact, "java") => steps.extend([
+            argv(&["mkdir", "-p", "/tmp/classes"]),
+            argv(&["cp", "-r", "--", &format!("{}/.", artifact), "/tmp/classes"]),
+        ]),
+        ("", _) | ("/tmp/main", _) => steps.push(argv(&["test", "-f", "/tmp/main"])),
+        (artifact, _) => steps.push(argv(&["cp", "--", artifact, "/tmp/main"])),
+    }
+    if language == "java" {
+        steps.extend(java_bundle());
+    }
+    Some(steps)
+}
+
+/// Steps that pack the classes in /tmp/classes into /tmp/java_bundle.tar,
+/// which is unpacked into the run container's program directory
+fn java_bundle() -> Vec<Argv> {
+    vec![argv(&[
+        "tar",
+        "-cf",
+        "/tmp/java_bundle.tar",
+        "-C",
+        "/tmp/classes",
+        ".",
+    ])]
+}
+
+/// An argv of fixed words
+fn argv(words: &[&str]) -> Argv {
+    words.iter().map(|word| word.to_string()).collect()
+}
+
+/// A compiler invocation: `head`, the submission's flags (checked against
+/// the allow-list by the master), then `tail`
+fn compiler_argv(head: &[&str], flags: &[String], tail: Argv) -> Argv {
+    [argv(head), flags.to_vec(), tail].concat()
+}
+
+/// Command that runs a compiled artifact installed by `install`
+fn binary_command(language: &str) -> &'static str {
+    if canonical_language(language) == Some("java") {
+        // Java runs the compiled .class file
+        "java -cp /box Main"
+    } else {
+        "/box/main"
+    }
+}
+
+/// Create a tar archive of files with the same mode. Paths of any length are
+/// written, as GNU long-name entries where needed.
+fn tar_archive(files: &[(&str, &[u8])], mode: u32) -> Result<Vec<u8>, String> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, content) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(mode);
+        builder
+            .append_data(&mut header, path, *content)
+            .map_err(|e| format!("Failed to archive {}: {}", path, e))?;
+    }
+    builder
+        .into_inner()
+        .map_err(|e| format!("Failed to archive files: {}", e))
+}
+
+/// Create a tar archive containing a single read-only file
+fn create_tar_archive(filename: &str, content: &[u8]) -> Result<Vec<u8>, String> {
+    tar_archive(&[(filename, content)], 0o444)
+}
+
+/// Create a tar archive containing several files
+fn create_tar_archive_files(files: &[(&str, &[u8])]) -> Result<Vec<u8>, String> {
+    tar_archive(files, 0o644)
+}
+
+/// Create a tar archive with a read-only executable file
+fn create_tar_archive_executable(filename: &str, content: &[u8]) -> Result<Vec<u8>, String> {
+    tar_archive(&[(filename, content)], 0o555)
 }
 
 /// Extract the first file from a tar archive
@@ -593,12 +1491,100 @@ fn extract_from_tar(data: &[u8]) -> Option<Vec<u8>> {
     use std::io::Read;
 
     let mut archive = tar::Archive::new(data);
-    for entry in archive.entries().ok()? {
-        if let Ok(mut entry) = entry {
-            let mut content = Vec::new();
-            entry.read_to_end(&mut content).ok()?;
-            return Some(content);
-        }
+    if let Some(mut entry) = archive.entries().ok()?.flatten().next() {
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).ok()?;
+        return Some(content);
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn tar_archive_keeps_long_paths() {
+        // The longest path the master accepts, under the worker's `src/`
+        let project_path = "d/".repeat(MAX_PROJECT_PATH_LEN / 2 - 4) + "main.cpp";
+        assert!(project_path.len() <= MAX_PROJECT_PATH_LEN);
+        let path = format!("src/{}", project_path);
+        assert!(path.len() > 100);
+
+        let archive = create_tar_archive_files(&[(&path, b"int main() {}")]).unwrap();
+        let mut archive = tar::Archive::new(archive.as_slice());
+        let mut entries = archive.entries().unwrap();
+        let mut entry = entries.next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap().to_str(), Some(path.as_str()));
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "int main() {}");
+        assert!(entries.next().is_none());
+    }
+
+    /// A program that kills itself is a runtime error, not MLE, unless the
+    /// memory cgroup killed it
+    #[test]
+    fn sigkill_is_mle_only_when_oom_killed() {
+        let mut stderr = "boom\n__RUSAGE__ 137 5 2048 0 0\n".to_string();
+        let usage = take_usage(&mut stderr).unwrap();
+        assert_eq!(stderr, "boom");
+        assert!(!usage.over_time(1000));
+        assert!(!usage.out_of_memory(64));
+
+        let mut stderr = "\n__RUSAGE__ 137 5 2048 0 1\n".to_string();
+        assert!(take_usage(&mut stderr).unwrap().out_of_memory(64));
+    }
+
+    #[test]
+    fn tar_archive_rejects_bad_paths() {
+        assert!(create_tar_archive("../main", b"").is_err());
+    }
+
+    #[test]
+    fn installed_files_are_read_only() {
+        for archive in [
+            create_tar_archive("main.py", b"print(1)").unwrap(),
+            create_tar_archive_executable("main", b"\x7fELF").unwrap(),
+        ] {
+            let mut archive = tar::Archive::new(archive.as_slice());
+            let entry = archive.entries().unwrap().next().unwrap().unwrap();
+            assert_eq!(entry.header().mode().unwrap() & 0o222, 0);
+        }
+    }
+
+    /// A test must not be able to replace the runner or the program for the
+    /// tests after it in a shared container
+    #[tokio::test]
+    #[ignore = "needs a Docker daemon and debian:bookworm-slim"]
+    async fn tests_cannot_write_program_dir() {
+        let executor = DockerExecutor::new().unwrap();
+        let program = Program {
+            source: Some(("main.py", "print(1)")),
+            binary: None,
+            is_java: false,
+            runner: Some(b"#!/bin/sh\n"),
+        };
+        let container = format!("run_sandbox_test_{}", Uuid::new_v4().simple());
+        executor
+            .start_sandbox(&container, "debian:bookworm-slim", 64, &program, true)
+            .await
+            .unwrap();
+
+        for cmd in [
+            "echo x > /box/.runner",
+            "echo x > /box/main.py",
+            "echo x > /box/new",
+            "rm -f /box/.runner",
+            "chmod 777 /box",
+        ] {
+            let (exit_code, _, _) = executor
+                .run_with_input(&container, cmd, None, vec![], Duration::from_secs(10))
+                .await
+                .unwrap();
+            assert_ne!(exit_code, 0, "{}", cmd);
+        }
+        executor.pool.release(&container).await;
+    }
+}
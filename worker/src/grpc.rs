@@ -4,14 +4,17 @@
 
 use common::scheduler::{
     master_command::Task, worker_message::Payload, worker_service_client::WorkerServiceClient,
-    Heartbeat, Register, WorkerMessage,
+    BatchExecutionResult, Heartbeat, Register, TestCaseProgress, WorkerMessage,
 };
 use crate::docker::DockerExecutor;
 use crate::metrics::MetricsCollector;
+use crate::suites::SuiteCache;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
 use tokio::time::interval;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::Channel;
@@ -20,13 +23,35 @@ use tracing::{error, info, warn};
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(2);
 const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Largest message accepted from the Master (test suites can be big)
+const MAX_MESSAGE_BYTES: usize = 256 * 1024 * 1024;
 
 pub struct GrpcClient {
     worker_id: String,
     master_addr: String,
     metrics: MetricsCollector,
     docker: Arc<DockerExecutor>,
+    suites: Arc<SuiteCache>,
     active_tasks: Arc<AtomicU32>,
+    /// Spawned compile/execute tasks per job, so a cancel (or losing the
+    /// Master) can abort them
+    running: Arc<Mutex<HashMap<String, Vec<AbortHandle>>>>,
+}
+
+/// Counts a task in `active_tasks` for as long as it lives (including when aborted)
+struct ActiveTask(Arc<AtomicU32>);
+
+impl ActiveTask {
+    fn start(counter: &Arc<AtomicU32>) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(Arc::clone(counter))
+    }
+}
+
+impl Drop for ActiveTask {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl GrpcClient {
@@ -36,7 +61,43 @@ impl GrpcClient {
             master_addr,
             metrics: MetricsCollector::new(),
             docker,
+            suites: Arc::new(SuiteCache::from_env()),
             active_tasks: Arc::new(AtomicU32::new(0)),
+            running: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Remember a spawned task of `job_id`, dropping handles of finished ones
+    fn track(&self, job_id: &str, handle: AbortHandle) {
+        let mut running = self.running.lock().unwrap();
+        running.retain(|_, handles| {
+            handles.retain(|h| !h.is_finished());
+            !handles.is_empty()
+        });
+        running.entry(job_id.to_string()).or_default().push(handle);
+    }
+
+    /// Abort every tracked task and remove its job's containers once the
+    /// stream is gone: the Master re-queues that work, possibly back to this
+    /// worker under the same container names
+    async fn abort_all(&self) {
+        let running: Vec<_> = self.running.lock().unwrap().drain().collect();
+        for (job_id, handles) in running {
+            let live: Vec<_> = handles.iter().filter(|h| !h.is_finished()).collect();
+            if live.is_empty() {
+                continue;
+            }
+            for handle in &live {
+                handle.abort();
+            }
+
+            let removed = self.docker.remove_job_containers(&job_id).await;
+            info!(
+                job_id = %job_id,
+                aborted_tasks = live.len(),
+                containers = removed,
+                "Aborted job's tasks after losing the Master"
+            );
         }
     }
 
@@ -50,7 +111,9 @@ impl GrpcClient {
                 "Connecting to Master..."
             );
 
-            match self.connect_and_process().await {
+            let result = self.connect_and_process().await;
+            self.abort_all().await;
+            match result {
                 Ok(_) => {
                     info!("Connection closed gracefully");
                     retry_count = 0;
@@ -83,7 +146,10 @@ impl GrpcClient {
             .connect()
             .await?;
 
-        let mut client = WorkerServiceClient::new(channel);
+        let mut client =
+            WorkerServiceClient::new(channel).max_decoding_message_size(MAX_MESSAGE_BYTES);
+        // Used by tasks to fetch test suites
+        let suite_client = client.clone();
 
         // Create channel for sending messages to master
         let (tx, rx) = mpsc::channel::<WorkerMessage>(32);
@@ -93,6 +159,19 @@ impl GrpcClient {
         let response = client.register_stream(rx_stream).await?;
         let mut inbound = response.into_inner();
 
+        // Detect which languages we can compile/run from the locally available images
+        let (compile_languages, run_languages) = self.docker.probe_capabilities().await;
+        info!(
+            compile = ?compile_languages,
+            run = ?run_languages,
+            "Detected language capabilities"
+        );
+
+        let mut tags = Vec::new();
+        if !compile_languages.is_empty() {
+            tags.push("can_compile".to_string());
+        }
+
         // Send registration
         self.metrics.refresh();
         let register_msg = WorkerMessage {
@@ -100,7 +179,10 @@ impl GrpcClient {
                 worker_id: self.worker_id.clone(),
                 cpu_cores: self.metrics.cpu_cores(),
                 total_ram_mb: self.metrics.total_ram_mb(),
-                tags: vec!["can_compile".to_string()], // TODO: detect capabilities
+                tags,
+                execution_slots: self.metrics.execution_slots(),
+                compile_languages,
+                run_languages,
             })),
         };
         tx.send(register_msg).await?;
@@ -110,6 +192,7 @@ impl GrpcClient {
         let heartbeat_tx = tx.clone();
         let worker_id = self.worker_id.clone();
         let active_tasks_hb = Arc::clone(&self.active_tasks);
+        let docker_hb = Arc::clone(&self.docker);
         let mut metrics = MetricsCollector::new();
         let heartbeat_handle = tokio::spawn(async move {
             let mut interval = interval(HEARTBEAT_INTERVAL);
@@ -123,6 +206,7 @@ impl GrpcClient {
                         cpu_load_percent: metrics.cpu_load_percent(),
                         ram_usage_mb: metrics.ram_usage_mb(),
                         active_tasks: active_tasks_hb.load(Ordering::Relaxed),
+                        pools: docker_hb.pool_stats(),
                     })),
                 };
 
@@ -145,31 +229,26 @@ impl GrpcClient {
                         info!(
                             job_id = %compile_task.job_id,
                             language = %compile_task.language,
+                            checker = compile_task.checker,
                             "Received compile task"
                         );
 
-                        active_tasks.fetch_add(1, Ordering::Relaxed);
-                        let active_tasks_clone = Arc::clone(&active_tasks);
+                        let job_id = compile_task.job_id.clone();
+                        let active = ActiveTask::start(&active_tasks);
 
-                        tokio::spawn(async move {
-                            let result = docker
-                                .compile(
-                                    &compile_task.job_id,
-                                    &compile_task.language,
-                                    &compile_task.source_code,
-                                    &compile_task.flags,
-                                )
-                                .await;
+                        let handle = tokio::spawn(async move {
+                            let _active = active;
+                            let result = docker.compile(&compile_task).await;
 
                             let msg = WorkerMessage {
                                 payload: Some(Payload::CompileResult(result)),
                             };
                             let _ = tx.send(msg).await;
-                            active_tasks_clone.fetch_sub(1, Ordering::Relaxed);
                         });
+                        self.track(&job_id, handle.abort_handle());
                     }
 
-                    Task::Execute(exec_task) => {
+                    Task::Execute(mut exec_task) => {
                         info!(
                             job_id = %exec_task.job_id,
                             batch_id = %exec_task.batch_id,
@@ -177,39 +256,96 @@ impl GrpcClient {
                             "Received execute task"
                         );
 
-                        active_tasks.fetch_add(1, Ordering::Relaxed);
-                        let active_tasks_clone = Arc::clone(&active_tasks);
+                        let job_id = exec_task.job_id.clone();
+                        let active = ActiveTask::start(&active_tasks);
+                        let suites = Arc::clone(&self.suites);
+                        let mut suite_client = suite_client.clone();
 
-                        tokio::spawn(async move {
-                            let (binary, source) = match exec_task.payload {
-                                Some(common::scheduler::execute_batch_task::Payload::BinaryArtifact(b)) => {
-                                    (Some(b), None)
-                                }
-                                Some(common::scheduler::execute_batch_task::Payload::SourceCode(s)) => {
-                                    (None, Some(s))
+                        let handle = tokio::spawn(async move {
+                            let _active = active;
+
+                            let resolved = suites.resolve(&mut suite_client, &mut exec_task).await;
+                            if let Err(e) = resolved {
+                                warn!(
+                                    job_id = %exec_task.job_id,
+                                    batch_id = %exec_task.batch_id,
+                                    error = %e,
+                                    "Failed to load test suite"
+                                );
+                                let msg = WorkerMessage {
+                                    payload: Some(Payload::BatchResult(BatchExecutionResult {
+                                        job_id: exec_task.job_id,
+                                        batch_id: exec_task.batch_id,
+                                        worker_id,
+                                        results: vec![],
+                                        metrics: None,
+                                        system_error: e,
+                                    })),
+                                };
+                                let _ = tx.send(msg).await;
+                                return;
+                            }
+
+                            // Forward each test result as it finishes; the batch
+                            // result is sent only after all of them
+                            let (progress_tx, mut progress_rx) = mpsc::channel(32);
+                            let progress_out = tx.clone();
+                            let job_id = exec_task.job_id.clone();
+                            let batch_id = exec_task.batch_id.clone();
+                            let forwarder = tokio::spawn(async move {
+                                while let Some(result) = progress_rx.recv().await {
+                                    let msg = WorkerMessage {
+                                        payload: Some(Payload::TestResult(TestCaseProgress {
+                                            job_id: job_id.clone(),
+                                            batch_id: batch_id.clone(),
+                                            result: Some(result),
+                                        })),
+                                    };
+                                    if progress_out.send(msg).await.is_err() {
+                                        break;
+                                    }
                                 }
-                                None => (None, None),
-                            };
+                            });
 
                             let result = docker
-                                .execute_batch(
-                                    &exec_task.job_id,
-                                    &exec_task.batch_id,
-                                    &worker_id,
-                                    &exec_task.language,
-                                    binary.as_deref(),
-                                    source.as_deref(),
-                                    &exec_task.inputs,
-                                    exec_task.time_limit_ms,
-                                    exec_task.memory_limit_mb,
-                                )
+                                .execute_batch(&worker_id, &exec_task, &progress_tx)
                                 .await;
+                            drop(progress_tx);
+                            let _ = forwarder.await;
 
                             let msg = WorkerMessage {
                                 payload: Some(Payload::BatchResult(result)),
                             };
                             let _ = tx.send(msg).await;
-                            active_tasks_clone.fetch_sub(1, Ordering::Relaxed);
+                        });
+                        self.track(&job_id, handle.abort_handle());
+                    }
+
+                    Task::Cancel(cancel) => {
+                        let handles = self
+                            .running
+                            .lock()
+                            .unwrap()
+                            .remove(&cancel.job_id)
+                            .unwrap_or_default();
+                        for handle in &handles {
+                            handle.abort();
+                        }
+
+                        info!(
+                            job_id = %cancel.job_id,
+                            aborted_tasks = handles.len(),
+                            "Cancelling job"
+                        );
+
+                        // Containers outlive the aborted tasks; remove them explicitly
+                        tokio::spawn(async move {
+                            let removed = docker.remove_job_containers(&cancel.job_id).await;
+                            info!(
+                                job_id = %cancel.job_id,
+                                containers = removed,
+                                "Removed cancelled job's containers"
+                            );
                         });
                     }
 
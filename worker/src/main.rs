@@ -5,9 +5,13 @@
 //! - Sends periodic heartbeats with system metrics
 //! - Executes compilation and code execution tasks in Docker
 
+mod compare;
 mod docker;
 mod grpc;
 mod metrics;
+mod pool;
+mod sandbox;
+mod suites;
 
 use docker::DockerExecutor;
 use grpc::GrpcClient;
@@ -46,6 +50,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Docker connection established");
 
+    // Containers pooled by a worker that crashed are never handed out again
+    docker.remove_orphaned_containers().await;
+
     // Create and run gRPC client
     let mut client = GrpcClient::new(worker_id, master_addr, docker);
     client.run().await;
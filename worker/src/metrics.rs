@@ -4,6 +4,9 @@
 
 use sysinfo::System;
 
+/// RAM budgeted per execution slot in MB (the build container limit)
+const SLOT_RAM_MB: u64 = 512;
+
 pub struct MetricsCollector {
     system: System,
 }
@@ -45,6 +48,12 @@ impl MetricsCollector {
     pub fn cpu_cores(&self) -> u32 {
         self.system.cpus().len() as u32
     }
+
+    /// Number of tasks to run concurrently: one per core, bounded by RAM
+    pub fn execution_slots(&self) -> u32 {
+        let by_ram = (self.total_ram_mb() / SLOT_RAM_MB) as u32;
+        self.cpu_cores().min(by_ram).max(1)
+    }
 }
 
 impl Default for MetricsCollector {
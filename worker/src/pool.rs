@@ -0,0 +1,653 @@
+//! Worker Node - Warm Container Pools
+//!
+//! Creating and starting a container dominates the latency of short tasks,
+//! so the worker keeps idle, started containers per image and hands them out
+//! for compile and execute tasks. A container is renamed to the task's name
+//! while leased, so it is addressed (and cancelled) like a fresh one.
+//!
+//! Build containers run as root with a writable root filesystem and may have
+//! run a submission's build, so they are used once: only never-used ones are
+//! kept warm. When a task gives a run container back it is restarted, which
+//! kills whatever is left running and empties its tmpfs mounts, its `/tmp` is
+//! emptied and it is only reused if nothing else in its filesystem differs
+//! from the image. Containers are recycled after a number of uses or an age
+//! limit; a background task refills pools.
+
+use crate::sandbox;
+use bollard::container::{
+    Config, CreateContainerOptions, ListContainersOptions, RemoveContainerOptions,
+    RenameContainerOptions, RestartContainerOptions, StartContainerOptions, UpdateContainerOptions,
+};
+use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::service::HostConfig;
+use bollard::Docker;
+use common::scheduler::PoolStats;
+use futures::StreamExt;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const DEFAULT_BUILD_SIZE: u32 = 1;
+const DEFAULT_RUN_SIZE: u32 = 2;
+const DEFAULT_MAX_USES: u32 = 50;
+const DEFAULT_MAX_AGE_SECS: u64 = 600;
+
+/// How often pools are checked for expired containers and refilled, unless
+/// a checkout wakes the refill task earlier
+const REFILL_INTERVAL: Duration = Duration::from_secs(5);
+/// Memory limit of idle run containers; set to the task's on checkout
+const IDLE_RUN_MEMORY_MB: u32 = 256;
+/// How long a container outlives its age limit before stopping by itself,
+/// should the worker die without removing it (covers the longest task)
+const ORPHAN_GRACE_SECS: u64 = 300;
+/// Label marking pooled containers, with their kind as the value; labelled
+/// containers left by a crashed worker are removed when it starts again
+const POOL_LABEL: &str = "judge.pool";
+
+/// Pool sizes and recycling limits
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    /// Idle containers kept per build image
+    pub build_size: u32,
+    /// Idle containers kept per run image
+    pub run_size: u32,
+    /// Uses after which a run container is removed instead of reset
+    pub max_uses: u32,
+    /// Age after which a container is removed
+    pub max_age: Duration,
+}
+
+impl PoolConfig {
+    /// Read `POOL_BUILD_SIZE`, `POOL_RUN_SIZE`, `POOL_MAX_USES` and
+    /// `POOL_MAX_AGE_SECS`, falling back to the defaults. Sizes of 0 turn
+    /// pooling off for that kind.
+    pub fn from_env() -> Self {
+        fn read<T: std::str::FromStr>(name: &str, default: T) -> T {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            build_size: read("POOL_BUILD_SIZE", DEFAULT_BUILD_SIZE),
+            run_size: read("POOL_RUN_SIZE", DEFAULT_RUN_SIZE),
+            max_uses: read("POOL_MAX_USES", DEFAULT_MAX_USES).max(1),
+            max_age: Duration::from_secs(read("POOL_MAX_AGE_SECS", DEFAULT_MAX_AGE_SECS)),
+        }
+    }
+
+    fn size(&self, kind: PoolKind) -> u32 {
+        match kind {
+            PoolKind::Build => self.build_size,
+            PoolKind::Run => self.run_size,
+        }
+    }
+}
+
+/// What a container is used for, which decides its limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PoolKind {
+    /// Compiling submissions, checkers and the runner
+    Build,
+    /// Running submissions, checkers and interactors
+    Run,
+}
+
+impl PoolKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PoolKind::Build => "build",
+            PoolKind::Run => "run",
+        }
+    }
+
+    /// Limits of a container of this kind; only run containers (hardened, see
+    /// `sandbox`) take a memory limit, build containers always get 512MB
+    fn host_config(&self, memory_mb: u32) -> HostConfig {
+        match self {
+            PoolKind::Build => HostConfig {
+                memory: Some(512 * 1024 * 1024), // 512MB
+                nano_cpus: Some(2_000_000_000),  // 2 CPUs
+                network_mode: Some("none".to_string()),
+                ..Default::default()
+            },
+            PoolKind::Run => sandbox::host_config(memory_mb),
+        }
+    }
+
+    /// Whether a container of this kind may go back to its pool after a task:
+    /// only hardened ones, whose tasks cannot change what a reset misses
+    fn reusable(&self) -> bool {
+        *self == PoolKind::Run
+    }
+
+    /// User the container's processes run as, if not the image's
+    fn user(&self) -> Option<String> {
+        match self {
+            PoolKind::Build => None,
+            PoolKind::Run => Some(sandbox::USER.to_string()),
+        }
+    }
+}
+
+type PoolKey = (PoolKind, String);
+
+/// A started container, idle or leased
+struct Pooled {
+    id: String,
+    key: PoolKey,
+    uses: u32,
+    created: Instant,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: u64,
+    misses: u64,
+    recycled: u64,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// Pools kept warm, in the order they are reported
+    warm: Vec<PoolKey>,
+    idle: HashMap<PoolKey, VecDeque<Pooled>>,
+    /// Leased containers by the name they were given
+    leased: HashMap<String, Pooled>,
+    counters: HashMap<PoolKey, Counters>,
+}
+
+pub struct ContainerPool {
+    docker: Docker,
+    config: PoolConfig,
+    state: Mutex<PoolState>,
+    /// Wakes the refill task
+    refill: Notify,
+    started: AtomicBool,
+}
+
+impl ContainerPool {
+    pub fn new(docker: Docker, config: PoolConfig) -> Arc<Self> {
+        Arc::new(Self {
+            docker,
+            config,
+            state: Mutex::new(PoolState::default()),
+            refill: Notify::new(),
+            started: AtomicBool::new(false),
+        })
+    }
+
+    /// Keep idle containers of these kinds and images, starting the refill
+    /// task on the first call
+    pub fn keep_warm(self: &Arc<Self>, pools: Vec<(PoolKind, &str)>) {
+        let warm: Vec<PoolKey> = pools
+            .into_iter()
+            .filter(|(kind, _)| self.config.size(*kind) > 0)
+            .map(|(kind, image)| (kind, image.to_string()))
+            .collect();
+        info!(
+            pools = warm.len(),
+            build_size = self.config.build_size,
+            run_size = self.config.run_size,
+            max_uses = self.config.max_uses,
+            max_age_secs = self.config.max_age.as_secs(),
+            "Keeping warm container pools"
+        );
+        self.state.lock().unwrap().warm = warm;
+
+        if !self.started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(Arc::clone(self).refill_loop());
+        } else {
+            self.refill.notify_one();
+        }
+    }
+
+    /// Hand out a started container of `kind` running `image` as `name`:
+    /// an idle one if there is one, otherwise a new one. Run containers are
+    /// limited to `memory_mb`. A stale container still named `name` is
+    /// removed.
+    pub async fn checkout(
+        &self,
+        kind: PoolKind,
+        image: &str,
+        name: &str,
+        memory_mb: u32,
+    ) -> Result<(), String> {
+        let key = (kind, image.to_string());
+
+        while let Some(container) = self.take_idle(&key) {
+            if self.expired(&container) {
+                self.recycle(&container).await;
+                continue;
+            }
+            if let Err(e) = self.claim_name(&container.id, name).await {
+                // The container is still good
+                self.push_idle(container);
+                return Err(e);
+            }
+            match self.limit_memory(&container, memory_mb).await {
+                Ok(()) => {
+                    self.lease(name, container, true);
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!(container = %container.id, error = %e, "Discarding pooled container");
+                    self.recycle(&container).await;
+                }
+            }
+        }
+
+        let id = self.create(kind, image, name, memory_mb).await?;
+        let container = Pooled {
+            id,
+            key,
+            uses: 0,
+            created: Instant::now(),
+        };
+        self.lease(name, container, false);
+        Ok(())
+    }
+
+    /// Create a started container of `kind` running `image` as `name` that
+    /// neither comes from nor goes back to a pool: `release` removes it
+    pub async fn checkout_fresh(
+        &self,
+        kind: PoolKind,
+        image: &str,
+        name: &str,
+        memory_mb: u32,
+    ) -> Result<(), String> {
+        self.create(kind, image, name, memory_mb).await.map(|_| ())
+    }
+
+    /// Take back the container handed out as `name`; `name` is free again
+    /// when this returns. A reusable container not due for recycling is reset
+    /// in the background and becomes idle once verified clean; one from
+    /// `checkout_fresh` is removed.
+    pub async fn release(self: &Arc<Self>, name: &str) {
+        let leased = self.state.lock().unwrap().leased.remove(name);
+        let Some(mut container) = leased else {
+            let _ = self.remove_container(name).await;
+            return;
+        };
+        container.uses += 1;
+
+        let keep =
+            container.key.0.reusable() && !self.expired(&container) && self.is_warm(&container.key);
+        let pool_name = pool_name(container.key.0);
+        if !keep || self.rename(&container.id, &pool_name).await.is_err() {
+            self.recycle(&container).await;
+            return;
+        }
+
+        let pool = Arc::clone(self);
+        tokio::spawn(async move {
+            match pool.reset(&container.id).await {
+                Ok(()) => pool.push_idle(container),
+                Err(e) => {
+                    info!(container = %container.id, reason = %e, "Recycling container");
+                    pool.recycle(&container).await;
+                }
+            }
+        });
+    }
+
+    /// Force-remove the container handed out as `name`, e.g. when its job is
+    /// cancelled
+    pub async fn remove(&self, name: &str) -> Result<(), String> {
+        let leased = self.state.lock().unwrap().leased.remove(name);
+        if let Some(container) = leased {
+            self.count(&container.key, |c| c.recycled += 1);
+        }
+        self.remove_container(name).await
+    }
+
+    /// Remove pooled containers left behind by an earlier run of the worker,
+    /// which owns the pooled containers of its Docker daemon. Returns how
+    /// many were removed.
+    pub async fn remove_orphans(&self) -> usize {
+        let mut filters = HashMap::new();
+        filters.insert("label".to_string(), vec![POOL_LABEL.to_string()]);
+
+        let containers = match self
+            .docker
+            .list_containers(Some(ListContainersOptions {
+                all: true,
+                filters,
+                ..Default::default()
+            }))
+            .await
+        {
+            Ok(containers) => containers,
+            Err(e) => {
+                warn!(error = %e, "Failed to list orphaned pool containers");
+                return 0;
+            }
+        };
+
+        let mut removed = 0;
+        for id in containers.into_iter().filter_map(|c| c.id) {
+            match self.remove_container(&id).await {
+                Ok(()) => removed += 1,
+                Err(e) => warn!(container = %id, error = %e, "Failed to remove orphaned container"),
+            }
+        }
+        removed
+    }
+
+    /// Statistics of every pool that is kept warm or has been used
+    pub fn stats(&self) -> Vec<PoolStats> {
+        let state = self.state.lock().unwrap();
+        let mut keys = state.warm.clone();
+        for key in state.counters.keys() {
+            if !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+
+        keys.into_iter()
+            .map(|key| {
+                let counters = state.counters.get(&key);
+                PoolStats {
+                    kind: key.0.as_str().to_string(),
+                    image: key.1.clone(),
+                    idle: state.idle.get(&key).map_or(0, |idle| idle.len() as u32),
+                    leased: state.leased.values().filter(|c| c.key == key).count() as u32,
+                    hits: counters.map_or(0, |c| c.hits),
+                    misses: counters.map_or(0, |c| c.misses),
+                    recycled: counters.map_or(0, |c| c.recycled),
+                }
+            })
+            .collect()
+    }
+
+    /// Remove expired and surplus idle containers, then top every warm pool up
+    async fn refill_loop(self: Arc<Self>) {
+        loop {
+            let (stale, wanted) = {
+                let mut state = self.state.lock().unwrap();
+                let mut stale = Vec::new();
+                let mut wanted = Vec::new();
+                for key in state.warm.clone() {
+                    let size = self.config.size(key.0) as usize;
+                    let idle = state.idle.entry(key.clone()).or_default();
+                    let (expired, fresh): (Vec<_>, Vec<_>) =
+                        idle.drain(..).partition(|c| self.expired(c));
+                    idle.extend(fresh);
+                    stale.extend(expired);
+                    while idle.len() > size {
+                        stale.extend(idle.pop_front());
+                    }
+                    wanted.push((key, size - idle.len()));
+                }
+                (stale, wanted)
+            };
+
+            for container in &stale {
+                self.recycle(container).await;
+            }
+            for ((kind, image), count) in wanted {
+                for _ in 0..count {
+                    match self
+                        .create(kind, &image, &pool_name(kind), IDLE_RUN_MEMORY_MB)
+                        .await
+                    {
+                        Ok(id) => self.push_idle(Pooled {
+                            id,
+                            key: (kind, image.clone()),
+                            uses: 0,
+                            created: Instant::now(),
+                        }),
+                        Err(e) => {
+                            warn!(image = %image, error = %e, "Failed to refill container pool");
+                            break;
+                        }
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = self.refill.notified() => {}
+                _ = tokio::time::sleep(REFILL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Create and start a container under `name`
+    async fn create(
+        &self,
+        kind: PoolKind,
+        image: &str,
+        name: &str,
+        memory_mb: u32,
+    ) -> Result<String, String> {
+        let lifetime = self.config.max_age.as_secs() + ORPHAN_GRACE_SECS;
+        let config = Config {
+            image: Some(image.to_string()),
+            cmd: Some(vec!["sleep".to_string(), lifetime.to_string()]),
+            user: kind.user(),
+            labels: Some(HashMap::from([(
+                POOL_LABEL.to_string(),
+                kind.as_str().to_string(),
+            )])),
+            host_config: Some(kind.host_config(memory_mb)),
+            ..Default::default()
+        };
+
+        let options = || {
+            Some(CreateContainerOptions {
+                name: name.to_string(),
+                platform: None,
+            })
+        };
+        let created = match self.docker.create_container(options(), config.clone()).await {
+            Err(e) if is_conflict(&e) => {
+                self.remove_stale(name).await;
+                self.docker.create_container(options(), config).await
+            }
+            created => created,
+        };
+        let id = created
+            .map_err(|e| format!("Failed to create container: {}", e))?
+            .id;
+
+        if let Err(e) = self
+            .docker
+            .start_container(&id, None::<StartContainerOptions<String>>)
+            .await
+        {
+            let _ = self.remove_container(&id).await;
+            return Err(format!("Failed to start container: {}", e));
+        }
+        Ok(id)
+    }
+
+    /// Give a run container the task's memory limit
+    async fn limit_memory(&self, container: &Pooled, memory_mb: u32) -> Result<(), String> {
+        if container.key.0 == PoolKind::Run {
+            // No swap, as in `sandbox::host_config`
+            let memory = sandbox::memory_bytes(memory_mb);
+            self.docker
+                .update_container(
+                    &container.id,
+                    UpdateContainerOptions::<String> {
+                        memory: Some(memory),
+                        memory_swap: Some(memory),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .map_err(|e| format!("Failed to set memory limit: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Kill everything left running, empty `/tmp` and check that nothing
+    /// else differs from the image
+    async fn reset(&self, id: &str) -> Result<(), String> {
+        self.docker
+            .restart_container(id, Some(RestartContainerOptions { t: 0 }))
+            .await
+            .map_err(|e| format!("Failed to restart: {}", e))?;
+
+        let exit_code = self.exec(id, "find /tmp -mindepth 1 -delete").await?;
+        if exit_code != 0 {
+            return Err(format!("Emptying /tmp exited with {}", exit_code));
+        }
+
+        let changes = self
+            .docker
+            .container_changes(id)
+            .await
+            .map_err(|e| format!("Failed to list changes: {}", e))?
+            .unwrap_or_default();
+        match changes.iter().find(|change| change.path != "/tmp") {
+            Some(change) => Err(format!("{} was changed", change.path)),
+            None => Ok(()),
+        }
+    }
+
+    /// Run a command in a container to completion; returns its exit code
+    async fn exec(&self, id: &str, cmd: &str) -> Result<i64, String> {
+        let exec = self
+            .docker
+            .create_exec(
+                id,
+                CreateExecOptions {
+                    cmd: Some(vec!["sh", "-c", cmd]),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| format!("Failed to create exec: {}", e))?;
+
+        if let StartExecResults::Attached { mut output, .. } = self
+            .docker
+            .start_exec(&exec.id, None)
+            .await
+            .map_err(|e| format!("Exec failed: {}", e))?
+        {
+            while output.next().await.is_some() {}
+        }
+
+        let inspect = self.docker.inspect_exec(&exec.id).await.ok();
+        Ok(inspect.and_then(|i| i.exit_code).unwrap_or(-1))
+    }
+
+    /// Give a container a task's name, taking the name from a container
+    /// that still holds it
+    async fn claim_name(&self, id: &str, name: &str) -> Result<(), String> {
+        match self
+            .docker
+            .rename_container(id, RenameContainerOptions { name })
+            .await
+        {
+            Err(e) if is_conflict(&e) => {
+                self.remove_stale(name).await;
+                self.rename(id, name).await
+            }
+            renamed => renamed.map_err(|e| format!("Failed to rename container: {}", e)),
+        }
+    }
+
+    /// Remove a container left holding a task's name, by an earlier attempt
+    /// at the task that was aborted before it released the name
+    async fn remove_stale(&self, name: &str) {
+        warn!(container = %name, "Removing stale container holding a task's name");
+        if let Err(e) = self.remove(name).await {
+            warn!(container = %name, error = %e, "Failed to remove stale container");
+        }
+    }
+
+    async fn rename(&self, id: &str, name: &str) -> Result<(), String> {
+        self.docker
+            .rename_container(id, RenameContainerOptions { name })
+            .await
+            .map_err(|e| format!("Failed to rename container: {}", e))
+    }
+
+    async fn remove_container(&self, name: &str) -> Result<(), String> {
+        self.docker
+            .remove_container(
+                name,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .map_err(|e| format!("Failed to remove container: {}", e))
+    }
+
+    /// Remove a container for good
+    async fn recycle(&self, container: &Pooled) {
+        self.count(&container.key, |c| c.recycled += 1);
+        let _ = self.remove_container(&container.id).await;
+    }
+
+    fn take_idle(&self, key: &PoolKey) -> Option<Pooled> {
+        let mut state = self.state.lock().unwrap();
+        state.idle.get_mut(key)?.pop_back()
+    }
+
+    fn push_idle(&self, container: Pooled) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .idle
+            .entry(container.key.clone())
+            .or_default()
+            .push_back(container);
+    }
+
+    fn lease(&self, name: &str, container: Pooled, hit: bool) {
+        self.count(&container.key, |c| {
+            if hit {
+                c.hits += 1;
+            } else {
+                c.misses += 1;
+            }
+        });
+        self.state
+            .lock()
+            .unwrap()
+            .leased
+            .insert(name.to_string(), container);
+        self.refill.notify_one();
+    }
+
+    fn count(&self, key: &PoolKey, update: impl FnOnce(&mut Counters)) {
+        let mut state = self.state.lock().unwrap();
+        update(state.counters.entry(key.clone()).or_default());
+    }
+
+    fn is_warm(&self, key: &PoolKey) -> bool {
+        self.state.lock().unwrap().warm.contains(key)
+    }
+
+    fn expired(&self, container: &Pooled) -> bool {
+        container.uses >= self.config.max_uses || container.created.elapsed() >= self.config.max_age
+    }
+}
+
+/// Whether Docker refused a request because a container name is taken
+fn is_conflict(e: &bollard::errors::Error) -> bool {
+    matches!(
+        e,
+        bollard::errors::Error::DockerResponseServerError {
+            status_code: 409,
+            ..
+        }
+    )
+}
+
+/// A fresh name for an idle container
+fn pool_name(kind: PoolKind) -> String {
+    format!("pool_{}_{}", kind.as_str(), Uuid::new_v4().simple())
+}
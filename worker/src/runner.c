@@ -0,0 +1,135 @@
+/*
+ * Worker Node - Test Runner
+ *
+ * Runs one test of a submission inside its container and measures it:
+ *
+ *     runner <cpu_limit_ms> <wall_limit_ms> <command>
+ *
+ * The command runs under `sh -c` with the CPU time limit applied as
+ * RLIMIT_CPU, and is killed if it runs past the wall-clock limit. Once it
+ * exits, the runner appends a report to stderr:
+ *
+ *     \n__RUSAGE__ <exit_code> <cpu_ms> <peak_rss_kb> <timed_out> <oom_killed>\n
+ *
+ * where exit_code is 128 + signal if the command was killed, cpu_ms is user
+ * plus system time, peak_rss_kb the largest resident set of the command and
+ * its descendants, timed_out 1 if the wall-clock limit killed it, and
+ * oom_killed 1 if the container's memory cgroup OOM-killed a process while
+ * the command ran. The runner exits with the same code.
+ *
+ * The runner must be the only process in the container besides PID 1: when
+ * the command exits, it kills and reaps everything else, so processes left
+ * behind (or that escaped the command's process group) cannot slow down the
+ * next test.
+ */
+
+#include <errno.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/prctl.h>
+#include <sys/resource.h>
+#include <sys/time.h>
+#include <sys/types.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static volatile sig_atomic_t timed_out = 0;
+
+static void on_wall_limit(int sig) {
+    (void)sig;
+    timed_out = 1;
+    /* Everything but PID 1 and the runner itself */
+    kill(-1, SIGKILL);
+}
+
+/* OOM kills so far in the container's memory cgroup (v2, else v1) */
+static long oom_kills(void) {
+    const char *paths[] = {
+        "/sys/fs/cgroup/memory.events",
+        "/sys/fs/cgroup/memory/memory.oom_control",
+    };
+    for (size_t i = 0; i < sizeof(paths) / sizeof(paths[0]); i++) {
+        FILE *file = fopen(paths[i], "r");
+        if (file == NULL) {
+            continue;
+        }
+        char key[64];
+        long value, count = -1;
+        while (fscanf(file, "%63s %ld", key, &value) == 2) {
+            if (strcmp(key, "oom_kill") == 0) {
+                count = value;
+            }
+        }
+        fclose(file);
+        if (count >= 0) {
+            return count;
+        }
+    }
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 4) {
+        fprintf(stderr, "usage: runner <cpu_limit_ms> <wall_limit_ms> <command>\n");
+        return 2;
+    }
+    long limit_ms = atol(argv[1]);
+    long wall_ms = atol(argv[2]);
+
+    /* Orphaned descendants are reparented to the runner, which reaps them */
+    prctl(PR_SET_CHILD_SUBREAPER, 1);
+    long oom_kills_before = oom_kills();
+
+    pid_t pid = fork();
+    if (pid < 0) {
+        perror("fork");
+        return 2;
+    }
+    if (pid == 0) {
+        if (limit_ms > 0) {
+            /* Whole seconds, rounded up: SIGXCPU at the limit, SIGKILL a second later */
+            rlim_t seconds = (limit_ms + 999) / 1000;
+            struct rlimit limit = { seconds, seconds + 1 };
+            setrlimit(RLIMIT_CPU, &limit);
+        }
+        execl("/bin/sh", "sh", "-c", argv[3], (char *)NULL);
+        perror("exec");
+        _exit(127);
+    }
+
+    if (wall_ms > 0) {
+        struct sigaction action = { 0 };
+        action.sa_handler = on_wall_limit;
+        sigaction(SIGALRM, &action, NULL);
+        struct itimerval timer = { { 0, 0 }, { wall_ms / 1000, (wall_ms % 1000) * 1000 } };
+        setitimer(ITIMER_REAL, &timer, NULL);
+    }
+
+    int status;
+    struct rusage usage;
+    while (wait4(pid, &status, 0, &usage) < 0) {
+        if (errno != EINTR) {
+            perror("wait4");
+            return 2;
+        }
+    }
+
+    int oom_killed = oom_kills() > oom_kills_before;
+
+    /* Leave an idle sandbox: kill whatever the command left running and reap it */
+    struct itimerval off = { { 0, 0 }, { 0, 0 } };
+    setitimer(ITIMER_REAL, &off, NULL);
+    kill(-1, SIGKILL);
+    while (waitpid(-1, NULL, 0) > 0 || errno == EINTR) {
+    }
+
+    long cpu_ms = (usage.ru_utime.tv_sec + usage.ru_stime.tv_sec) * 1000L
+        + (usage.ru_utime.tv_usec + usage.ru_stime.tv_usec) / 1000L;
+    int code = WIFEXITED(status) ? WEXITSTATUS(status) : 128 + WTERMSIG(status);
+
+    fprintf(stderr, "\n__RUSAGE__ %d %ld %ld %d %d\n", code, cpu_ms, usage.ru_maxrss,
+            (int)timed_out, oom_killed);
+    return code;
+}
@@ -0,0 +1,116 @@
+//! Worker Node - Sandbox Profile
+//!
+//! Settings of the containers that run untrusted code (submissions, checkers
+//! and interactors), following the security table in DOC.md section 6: no
+//! network, a read-only root filesystem with size-limited tmpfs mounts, no
+//! capabilities or privilege escalation, a bundled seccomp profile, a
+//! non-root user, no swap and limits on processes, file size and open files.
+//!
+//! Docker cannot copy archives into tmpfs mounts, so files are unpacked into
+//! these containers by `tar` running inside them. Programs are unpacked as
+//! root into a root-owned program directory, so the code they run cannot
+//! replace them (or the runner) for later tests.
+
+use bollard::service::{HostConfig, ResourcesUlimits};
+use std::collections::HashMap;
+
+/// Where programs are installed: the only mount that allows exec, owned by
+/// root and read-only to `USER`
+pub const PROGRAM_DIR: &str = "/box";
+/// Scratch space for programs, and where checkers find their files
+pub const SCRATCH_DIR: &str = "/tmp";
+/// User programs run as (`nobody`)
+pub const USER: &str = "65534:65534";
+/// User files are unpacked as; with every capability dropped it can only
+/// write to the mounts because it owns `PROGRAM_DIR`
+pub const INSTALL_USER: &str = "0:0";
+/// Numeric id of `USER` (both user and group), which owns the scratch mount
+const USER_ID: u32 = 65534;
+/// Numeric id of `INSTALL_USER`, which owns the program mount
+const INSTALL_USER_ID: u32 = 0;
+
+/// Size of the program mount (binaries, sources, Java classes and the runner)
+const PROGRAM_DIR_MB: u32 = 64;
+/// Size of the scratch mount
+const SCRATCH_DIR_MB: u32 = 64;
+/// Largest file a program may write
+const MAX_FILE_BYTES: i64 = 64 * 1024 * 1024;
+/// Most files a process may have open
+const MAX_OPEN_FILES: i64 = 256;
+/// Most processes and threads in a container (stops fork bombs)
+const MAX_PIDS: i64 = 50;
+
+/// Seccomp allow-list: the syscalls of Docker's default profile, minus
+/// tracing, namespaces, keyrings, io_uring and listening sockets; `clone3`
+/// fails with ENOSYS so libc falls back to `clone`, whose namespace flags
+/// are denied
+const SECCOMP_PROFILE: &str = include_str!("seccomp.json");
+
+/// Host settings of a container that runs untrusted code
+pub fn host_config(memory_mb: u32) -> HostConfig {
+    let memory = memory_bytes(memory_mb);
+    let tmpfs = |options: &str, size_mb: u32, owner: u32| {
+        format!(
+            "rw,{},nosuid,nodev,size={}m,mode=755,uid={2},gid={2}",
+            options, size_mb, owner
+        )
+    };
+    let ulimit = |name: &str, limit: i64| ResourcesUlimits {
+        name: Some(name.to_string()),
+        soft: Some(limit),
+        hard: Some(limit),
+    };
+
+    HostConfig {
+        memory: Some(memory),
+        // Memory plus swap equal to memory: no swap
+        memory_swap: Some(memory),
+        nano_cpus: Some(1_000_000_000), // 1 CPU
+        network_mode: Some("none".to_string()),
+        pids_limit: Some(MAX_PIDS),
+        readonly_rootfs: Some(true),
+        tmpfs: Some(HashMap::from([
+            (
+                PROGRAM_DIR.to_string(),
+                tmpfs("exec", PROGRAM_DIR_MB, INSTALL_USER_ID),
+            ),
+            (
+                SCRATCH_DIR.to_string(),
+                tmpfs("noexec", SCRATCH_DIR_MB, USER_ID),
+            ),
+        ])),
+        cap_drop: Some(vec!["ALL".to_string()]),
+        security_opt: Some(vec![
+            "no-new-privileges".to_string(),
+            format!("seccomp={}", SECCOMP_PROFILE),
+        ]),
+        ulimits: Some(vec![
+            ulimit("fsize", MAX_FILE_BYTES),
+            ulimit("nofile", MAX_OPEN_FILES),
+        ]),
+        ..Default::default()
+    }
+}
+
+/// A memory limit in bytes
+pub fn memory_bytes(memory_mb: u32) -> i64 {
+    memory_mb as i64 * 1024 * 1024
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_dir_is_read_only_to_user() {
+        let tmpfs = host_config(64).tmpfs.unwrap();
+        let program_dir = &tmpfs[PROGRAM_DIR];
+        assert!(program_dir.contains("mode=755"), "{}", program_dir);
+        assert!(program_dir.contains("uid=0,gid=0"), "{}", program_dir);
+        assert!(!USER.starts_with("0:"));
+
+        // Programs still get scratch space of their own
+        let scratch_dir = &tmpfs[SCRATCH_DIR];
+        assert!(scratch_dir.contains(&format!("uid={0},gid={0}", USER_ID)));
+    }
+}
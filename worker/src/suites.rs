@@ -0,0 +1,103 @@
+//! Worker Node - Test Suite Cache
+//!
+//! Batches of jobs that run an uploaded suite carry only test ids and the
+//! suite's content hash. The suite's data is fetched from the Master on first
+//! use and kept on local disk, keyed by that hash.
+
+use common::scheduler::{
+    worker_service_client::WorkerServiceClient, ExecuteBatchTask, SuiteData, SuiteRequest, TestCase,
+};
+use prost::Message;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tonic::transport::Channel;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub struct SuiteCache {
+    dir: PathBuf,
+}
+
+impl SuiteCache {
+    /// Cache in `SUITE_CACHE_DIR`, or `worker-suites` in the temp directory
+    pub fn from_env() -> Self {
+        let dir = std::env::var("SUITE_CACHE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir().join("worker-suites"));
+        Self { dir }
+    }
+
+    /// Fill in a batch's inputs and expected outputs from its suite, if it has one
+    pub async fn resolve(
+        &self,
+        client: &mut WorkerServiceClient<Channel>,
+        task: &mut ExecuteBatchTask,
+    ) -> Result<(), String> {
+        if task.suite_hash.is_empty() {
+            return Ok(());
+        }
+
+        let suite = self.load(client, &task.suite_hash).await?;
+        let by_id: HashMap<&str, &TestCase> = suite
+            .test_cases
+            .iter()
+            .map(|tc| (tc.id.as_str(), tc))
+            .collect();
+
+        for tc in &mut task.inputs {
+            let data = by_id
+                .get(tc.id.as_str())
+                .ok_or_else(|| format!("Test {} is not in suite {}", tc.id, task.suite_hash))?;
+            tc.input = data.input.clone();
+            tc.expected_output = data.expected_output.clone();
+        }
+        Ok(())
+    }
+
+    /// Read a suite from disk, fetching it from the Master if missing or corrupt
+    async fn load(
+        &self,
+        client: &mut WorkerServiceClient<Channel>,
+        hash: &str,
+    ) -> Result<SuiteData, String> {
+        // The hash names a file; never let it point outside the cache
+        if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid suite hash: {}", hash));
+        }
+
+        let path = self.dir.join(format!("{}.pb", hash));
+        if let Ok(bytes) = tokio::fs::read(&path).await {
+            match SuiteData::decode(bytes.as_slice()) {
+                Ok(suite) if suite.hash == hash => return Ok(suite),
+                _ => warn!(hash = %hash, "Cached suite is corrupt, fetching it again"),
+            }
+        }
+
+        let suite = client
+            .get_suite(SuiteRequest {
+                hash: hash.to_string(),
+            })
+            .await
+            .map_err(|e| format!("Failed to fetch suite {}: {}", hash, e.message()))?
+            .into_inner();
+        info!(
+            hash = %hash,
+            test_cases = suite.test_cases.len(),
+            "Fetched test suite"
+        );
+
+        if let Err(e) = self.store(&path, &suite).await {
+            warn!(hash = %hash, error = %e, "Failed to cache suite");
+        }
+        Ok(suite)
+    }
+
+    /// Write a suite to a temporary file, then move it into place so readers
+    /// never see a partial file
+    async fn store(&self, path: &Path, suite: &SuiteData) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let tmp = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+        tokio::fs::write(&tmp, suite.encode_to_vec()).await?;
+        tokio::fs::rename(&tmp, path).await
+    }
+}